@@ -3,14 +3,19 @@
 # Translates standard MoQT interop environment variables to CLI arguments
 #
 # Expected environment:
-#   RELAY_URL          - URL of relay to test against (required)
-#   TESTCASE           - Specific test case to run (optional, runs all if not set)
+#   RELAY_URL          - URL of relay to test against (required; comma-separated for multi-relay tests)
+#   TESTCASE           - Test case(s) to run, comma-separated, globs allowed (optional, runs all if not set)
 #   TLS_DISABLE_VERIFY - Set to 1 or true to disable TLS verification
 #   VERBOSE            - Set to 1 or true for verbose output
 #
 # Exit codes:
-#   0 - All tests passed
-#   1 - One or more tests failed
+#   0   - All tests passed
+#   1   - One or more tests failed
+#   2   - Every failure was a timeout or connection error, mostly timeouts
+#   3   - Every failure was a connection or setup error (refused, TLS, ALPN,
+#         auth, version) or a timeout, and timeouts didn't outnumber the rest
+#   127 - Unsupported test case, or bad arguments
+#   130 - Interrupted (SIGINT); unfinished tests are reported as skipped
 
 set -euo pipefail
 
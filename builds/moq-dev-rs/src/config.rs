@@ -0,0 +1,72 @@
+//! Settings loaded from a `--config` TOML file.
+//!
+//! Everything is optional and overlays the compiled defaults; flags given on the
+//! command line (or through their environment variables) still win. A profile
+//! for a remote relay might look like:
+//!
+//! ```toml
+//! namespace = "moq-test/remote"
+//! connect-retries = 3
+//!
+//! [tests.throughput-benchmark]
+//! timeout-ms = 30000
+//!
+//! [tests.goaway-handling]
+//! enabled = false
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Like `--namespace`.
+    pub namespace: Option<String>,
+    /// Like `--track`.
+    pub track: Option<String>,
+    /// Like `--connect-retries`.
+    pub connect_retries: Option<u32>,
+    /// Settings for individual tests, by name.
+    pub tests: BTreeMap<String, TestConfig>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TestConfig {
+    /// Like `--timeout-override NAME=MS`, which wins if both are given.
+    pub timeout_ms: Option<u64>,
+    /// Leave the test out when no `--test` is given.
+    pub enabled: Option<bool>,
+}
+
+impl Config {
+    pub fn load(path: &Path, tests: &[&str]) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config: {}", path.display()))?;
+        let config: Self = toml::from_str(&text)
+            .with_context(|| format!("failed to parse config: {}", path.display()))?;
+
+        for name in config.tests.keys() {
+            anyhow::ensure!(
+                tests.contains(&name.as_str()),
+                "unknown test in config {}: {}",
+                path.display(),
+                name
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Tests the file disables.
+    pub fn disabled(&self) -> impl Iterator<Item = &str> {
+        self.tests
+            .iter()
+            .filter(|(_, test)| test.enabled == Some(false))
+            .map(|(name, _)| name.as_str())
+    }
+}
@@ -0,0 +1,871 @@
+//! QUIC connection setup for the test cases.
+//!
+//! moq-native's `Client::connect` only hands back the MoQ session, which hides the
+//! QUIC connection and with it any transport stats. This mirrors its quinn backend
+//! for the schemes we test against (WebTransport over `https://`, raw QUIC over
+//! `moqt://`/`moql://`) but keeps a handle to the connection around.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use moq_native::moq_lite;
+use moq_native::rustls;
+use moq_native::web_transport_quinn::{self, quinn};
+use rustls::pki_types::pem::PemObject;
+
+use crate::impair::{ImpairedSocket, Impairment};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+
+/// Default maximum number of concurrent QUIC streams, matching moq-native.
+const DEFAULT_MAX_STREAMS: u64 = 1024;
+
+/// Length of the initial destination connection ID we pick for each connection.
+const CONNECTION_ID_LEN: usize = 16;
+
+/// Delay before the first connection retry, doubled for each one after.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often to re-send the port probe while a handshake is in progress.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A control stream type no MoQ version defines.
+const INVALID_STREAM_TYPE: u8 = 0x3f;
+
+/// URL schemes we know how to connect to.
+pub const SCHEMES: &[&str] = &["https", "moqt", "moql"];
+
+/// The congestion controllers quinn ships with.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Congestion {
+    /// CUBIC (RFC 9438), quinn's default
+    Cubic,
+    /// NewReno (RFC 9002)
+    NewReno,
+    /// BBR, still experimental in quinn
+    Bbr,
+}
+
+impl Congestion {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Cubic => "cubic",
+            Self::NewReno => "new-reno",
+            Self::Bbr => "bbr",
+        }
+    }
+
+    fn factory(self) -> Arc<dyn quinn::congestion::ControllerFactory + Send + Sync> {
+        match self {
+            Self::Cubic => Arc::new(quinn::congestion::CubicConfig::default()),
+            Self::NewReno => Arc::new(quinn::congestion::NewRenoConfig::default()),
+            Self::Bbr => Arc::new(quinn::congestion::BbrConfig::default()),
+        }
+    }
+}
+
+/// Resolves with the error if nothing is listening on `addr`, and never otherwise.
+///
+/// quinn ignores the ICMP port unreachable a closed UDP port sends back, so a
+/// dead relay would only ever show up as a handshake timeout. A connected UDP
+/// socket does surface it, as `ConnectionRefused`, so we send a one-byte probe
+/// (too short for a QUIC server to answer) alongside the real handshake.
+async fn refused(addr: SocketAddr) -> std::io::Error {
+    let probe = async {
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = tokio::net::UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        // The kernel rate limits ICMP errors, and the handshake's own packets may
+        // have used them up, so keep probing until one gets through.
+        loop {
+            socket.send(&[0]).await?;
+            if let Ok(res) = tokio::time::timeout(PROBE_INTERVAL, socket.recv(&mut [0; 1])).await {
+                return res.map(|_| ());
+            }
+        }
+    };
+
+    match probe.await {
+        Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => err,
+        _ => std::future::pending().await,
+    }
+}
+
+/// Fail early on a relay URL we have no transport for.
+pub fn check_url(url: &url::Url) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        SCHEMES.contains(&url.scheme()),
+        "unsupported relay URL scheme '{}': expected one of {}",
+        url.scheme(),
+        SCHEMES.join(", ")
+    );
+    anyhow::ensure!(url.host().is_some(), "relay URL has no host: {}", url);
+    Ok(())
+}
+
+/// Whether the relay URL's host is an IPv6 address, like `https://[::1]:4443`.
+pub fn is_ipv6(url: &url::Url) -> bool {
+    matches!(url.host(), Some(url::Host::Ipv6(_)))
+}
+
+/// The same relay addressed over raw QUIC instead of WebTransport.
+pub fn raw_quic_url(url: &url::Url) -> anyhow::Result<url::Url> {
+    if url.scheme() != "https" {
+        return Ok(url.clone());
+    }
+
+    // `Url::set_scheme` refuses to switch between special and non-special schemes.
+    let raw = format!("moqt{}", &url.as_str()[url.scheme().len()..]);
+    let mut raw = url::Url::parse(&raw).context("failed to build raw QUIC URL")?;
+
+    // https:// omits the default port, but moqt:// has no default of its own.
+    if raw.port().is_none() {
+        raw.set_port(Some(443)).ok();
+    }
+    Ok(raw)
+}
+
+/// The same relay addressed over WebTransport instead of raw QUIC.
+pub fn webtransport_url(url: &url::Url) -> anyhow::Result<url::Url> {
+    if url.scheme() == "https" {
+        return Ok(url.clone());
+    }
+
+    // As above, but here `https://` brings its own default port too.
+    let web = format!("https{}", &url.as_str()[url.scheme().len()..]);
+    url::Url::parse(&web).context("failed to build WebTransport URL")
+}
+
+/// A PEM certificate chain and private key to present to relays requiring mutual TLS.
+pub struct ClientIdentity {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl ClientIdentity {
+    fn load(&self) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+        let chain = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .with_context(|| format!("failed to read client cert: {}", self.cert.display()))?;
+        anyhow::ensure!(
+            !chain.is_empty(),
+            "no certificates in client cert: {}",
+            self.cert.display()
+        );
+
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .with_context(|| format!("failed to read client key: {}", self.key.display()))?;
+
+        Ok((chain, key))
+    }
+}
+
+/// A client that can open any number of MoQ sessions to a relay.
+#[derive(Clone)]
+pub struct Client {
+    moq: moq_lite::Client,
+    versions: moq_lite::Versions,
+    quic: quinn::Endpoint,
+    tls: rustls::ClientConfig,
+    transport: Arc<quinn::TransportConfig>,
+    random: &'static dyn rustls::crypto::SecureRandom,
+    retries: u32,
+    /// Protocols to offer in place of the MoQ versions' own ALPNs.
+    alpns: Vec<String>,
+    /// The one version the config allows, if it narrowed them down to one.
+    pinned: Option<moq_lite::Version>,
+    zero_rtt: bool,
+    /// The congestion controller asked for, if not left to quinn.
+    congestion: Option<Congestion>,
+    impairment: Impairment,
+    auth_token: Option<String>,
+}
+
+impl Client {
+    /// Create a client from the same config moq-native uses, optionally
+    /// authenticating itself to the relay with a client certificate, picking
+    /// the congestion controller, and impairing what it sends.
+    pub fn new(
+        config: &moq_native::ClientConfig,
+        identity: Option<&ClientIdentity>,
+        congestion: Option<Congestion>,
+        impairment: Impairment,
+    ) -> anyhow::Result<Self> {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+
+        // The system roots, plus any given, so a private CA doesn't cost us
+        // the relays with public certificates.
+        let mut roots = rustls::RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for err in native.errors {
+            tracing::warn!(%err, "failed to load root cert");
+        }
+        for cert in native.certs {
+            roots.add(cert).context("failed to add root cert")?;
+        }
+
+        // Each file may be a bundle of several roots.
+        for root in &config.tls.root {
+            let certs = CertificateDer::pem_file_iter(root)
+                .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+                .with_context(|| format!("failed to read root cert: {}", root.display()))?;
+            anyhow::ensure!(
+                !certs.is_empty(),
+                "no certificates in root cert: {}",
+                root.display()
+            );
+            for cert in certs {
+                roots.add(cert).context("failed to add root cert")?;
+            }
+        }
+
+        let tls = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_root_certificates(roots);
+
+        let mut tls = match identity {
+            Some(identity) => {
+                let (chain, key) = identity.load()?;
+                tls.with_client_auth_cert(chain, key)
+                    .context("invalid client certificate or key")?
+            }
+            None => tls.with_no_client_auth(),
+        };
+
+        if config.tls.disable_verify.unwrap_or_default() {
+            tracing::warn!("TLS server certificate verification is disabled");
+            let noop = NoCertificateVerification(provider.clone());
+            tls.dangerous().set_certificate_verifier(Arc::new(noop));
+        }
+
+        let mut transport = quinn::TransportConfig::default();
+        transport.max_idle_timeout(Some(Duration::from_secs(10).try_into().unwrap()));
+        transport.keep_alive_interval(Some(Duration::from_secs(4)));
+        transport.mtu_discovery_config(None);
+        if let Some(congestion) = congestion {
+            transport.congestion_controller_factory(congestion.factory());
+        }
+
+        let max_streams = config.max_streams.unwrap_or(DEFAULT_MAX_STREAMS);
+        let max_streams = quinn::VarInt::from_u64(max_streams).unwrap_or(quinn::VarInt::MAX);
+        transport.max_concurrent_bidi_streams(max_streams);
+        transport.max_concurrent_uni_streams(max_streams);
+
+        // The default bind is dual-stack, reaching IPv4 relays through mapped
+        // addresses, but hosts with IPv6 disabled can't bind it at all.
+        let socket = match std::net::UdpSocket::bind(config.bind) {
+            Err(err) if config.bind.ip() == std::net::Ipv6Addr::UNSPECIFIED => {
+                tracing::warn!(%err, "failed to bind IPv6 socket, falling back to IPv4");
+                std::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, config.bind.port()))
+            }
+            socket => socket,
+        }
+        .with_context(|| format!("failed to bind UDP socket to {}", config.bind))?;
+        let runtime = quinn::default_runtime().context("no async runtime")?;
+        let mut socket = runtime
+            .wrap_udp_socket(socket)
+            .context("failed to wrap UDP socket")?;
+        if !impairment.is_none() {
+            tracing::warn!(impairment = %impairment.label(), "impairing outgoing datagrams");
+            socket = ImpairedSocket::new(socket, impairment);
+        }
+        let quic = quinn::Endpoint::new_with_abstract_socket(
+            quinn::EndpointConfig::default(),
+            None,
+            socket,
+            runtime,
+        )
+        .context("failed to create QUIC endpoint")?;
+
+        let versions = config.versions();
+        let pinned = match config.version.as_slice() {
+            [version] => Some(*version),
+            _ => None,
+        };
+
+        Ok(Self {
+            moq: moq_lite::Client::new().with_versions(versions.clone()),
+            versions,
+            quic,
+            tls,
+            transport: Arc::new(transport),
+            random: provider.secure_random,
+            retries: 0,
+            alpns: Vec::new(),
+            pinned,
+            zero_rtt: false,
+            congestion,
+            impairment,
+            auth_token: None,
+        })
+    }
+
+    pub fn with_publish(mut self, publish: impl Into<Option<moq_lite::OriginConsumer>>) -> Self {
+        self.moq = self.moq.with_publish(publish);
+        self
+    }
+
+    pub fn with_consume(mut self, consume: impl Into<Option<moq_lite::OriginProducer>>) -> Self {
+        self.moq = self.moq.with_consume(consume);
+        self
+    }
+
+    /// Retry connections that fail to establish up to this many times.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Offer these protocols instead of the ones for our MoQ versions: as the TLS
+    /// ALPN over raw QUIC, and in the CONNECT request over WebTransport, whose TLS
+    /// ALPN is always `h3`. Empty keeps the defaults.
+    pub fn with_alpns(mut self, alpns: Vec<String>) -> Self {
+        self.alpns = alpns;
+        self
+    }
+
+    /// Attempt 0-RTT when resuming a TLS session whose ticket allows it.
+    ///
+    /// Nothing is sent as early data: the handshake still finishes before the
+    /// MoQ session starts, so a rejection can't break it, and the connection
+    /// just records whether the relay would have accepted.
+    pub fn with_zero_rtt(mut self) -> Self {
+        self.zero_rtt = true;
+        self
+    }
+
+    /// Present this token to the relay, as the `jwt` query parameter of the
+    /// CONNECT request that moq-relay authenticates from.
+    ///
+    /// Raw QUIC has no request to carry it, so it's only sent over WebTransport.
+    pub fn with_auth_token(mut self, token: Option<String>) -> Self {
+        self.auth_token = token;
+        self
+    }
+
+    pub fn auth_token(&self) -> Option<&str> {
+        self.auth_token.as_deref()
+    }
+
+    /// The protocols to offer a relay.
+    fn protocols(&self) -> Vec<String> {
+        match self.alpns.is_empty() {
+            true => self
+                .versions
+                .alpns()
+                .iter()
+                .map(|alpn| alpn.to_string())
+                .collect(),
+            false => self.alpns.clone(),
+        }
+    }
+
+    /// Open a QUIC connection and perform the MoQ handshake over it, retrying
+    /// transient failures with exponential backoff.
+    ///
+    /// TLS and protocol errors aren't retried; they won't fix themselves.
+    pub async fn connect(&self, url: url::Url) -> anyhow::Result<Connection> {
+        check_url(&url)?;
+
+        let start = Instant::now();
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 1;
+
+        loop {
+            let err = match self.connect_once(url.clone()).await {
+                Ok(mut conn) => {
+                    conn.attempts = (self.retries > 0).then_some(attempt);
+                    conn.connect_time = start.elapsed();
+                    return Ok(conn);
+                }
+                Err(err) => err,
+            };
+
+            // Refusing the pinned version's ALPN is refusing the version, unless
+            // --alpn replaced it.
+            let err = match (crate::FailureKind::classify(&err), self.pinned) {
+                (crate::FailureKind::AlpnMismatch, Some(version)) if self.alpns.is_empty() => {
+                    err.context(crate::VersionRejected(version))
+                }
+                _ => err,
+            };
+
+            let transient = !matches!(
+                crate::FailureKind::classify(&err),
+                crate::FailureKind::TlsError
+                    | crate::FailureKind::AlpnMismatch
+                    | crate::FailureKind::ProtocolError
+                    | crate::FailureKind::Unauthorized
+                    | crate::FailureKind::VersionMismatch
+            );
+            if !transient || attempt > self.retries {
+                return Err(match attempt {
+                    1 => err,
+                    _ => err.context(format!("gave up after {} attempts", attempt)),
+                });
+            }
+
+            tracing::warn!(%url, attempt, ?backoff, "connection failed, retrying: {:#}", err);
+            tokio::time::sleep(backoff).await;
+
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    async fn connect_once(&self, url: url::Url) -> anyhow::Result<Connection> {
+        let Transport {
+            session: transport,
+            quic,
+            id,
+            alpn,
+            early_data,
+            authenticated,
+        } = self.open(&url).await?;
+
+        let session = self
+            .moq
+            .connect(transport.clone())
+            .await
+            .map_err(|err| closed_by_peer(err.into(), &quic, &alpn))?;
+        tracing::info!(version = %session.version(), %alpn, "connected");
+
+        Ok(Connection {
+            session,
+            transport: Arc::new(transport),
+            quic,
+            id,
+            alpn,
+            attempts: None,
+            connect_time: Duration::ZERO,
+            early_data,
+            congestion: self.congestion,
+            impairment: self.impairment,
+            authenticated,
+        })
+    }
+
+    /// Perform a draft-14 SETUP exchange by hand, offering `parameters`, and
+    /// return what the relay answered with. The connection is closed once it
+    /// has; there's no MoQ session to go on with.
+    pub async fn exchange_setup(
+        &self,
+        url: url::Url,
+        parameters: &[crate::setup::Parameter],
+    ) -> anyhow::Result<SetupExchange> {
+        check_url(&url)?;
+
+        // Draft-14's own ALPN, so the relay expects its SETUP rather than
+        // negotiating one of the versions that share `moql`.
+        let client = Self {
+            alpns: vec![moq_lite::ALPN_14.to_string()],
+            ..self.clone()
+        };
+
+        let start = Instant::now();
+        let transport = client.open(&url).await?;
+        let (mut send, mut recv) = transport
+            .session
+            .open_bi()
+            .await
+            .context("failed to open setup stream")?;
+        let connect_time = start.elapsed();
+
+        let setup = crate::setup::client_setup(&[crate::setup::DRAFT_14], parameters)?;
+        send.write_all(&setup)
+            .await
+            .context("failed to write client setup")?;
+        let server = crate::setup::read_server_setup(&mut recv).await;
+
+        let rtt = transport.quic.rtt();
+        transport.session.close(0, b"");
+
+        Ok(SetupExchange {
+            server: server?,
+            id: transport.id,
+            connect_time,
+            rtt,
+        })
+    }
+
+    /// Open a QUIC connection, and a WebTransport session over it for
+    /// `https://`, without starting MoQ.
+    async fn open(&self, url: &url::Url) -> anyhow::Result<Transport> {
+        let port = url.port().unwrap_or(443);
+        let local = self
+            .quic
+            .local_addr()
+            .context("failed to get local address")?;
+
+        // IP literals skip DNS, and go in the TLS server name without their brackets.
+        let (host, ip) = match url.host().context("invalid DNS name")? {
+            url::Host::Ipv4(ip) => (ip.to_string(), SocketAddr::new(ip.into(), port)),
+            url::Host::Ipv6(ip) => (ip.to_string(), SocketAddr::new(ip.into(), port)),
+            url::Host::Domain(domain) => {
+                // Quinn doesn't support happy eyeballs, so we use the first address
+                // our socket can reach: an IPv4 one can't send to IPv6 addresses.
+                let ip = tokio::net::lookup_host((domain, port))
+                    .await
+                    .context("failed DNS lookup")?
+                    .find(|addr| local.is_ipv6() || addr.is_ipv4())
+                    .context("no DNS entries this socket can reach")?;
+                (domain.to_string(), ip)
+            }
+        };
+        anyhow::ensure!(
+            local.is_ipv6() || ip.is_ipv4(),
+            "cannot reach IPv6 relay {} from IPv4 socket {}",
+            ip,
+            local
+        );
+
+        let alpns: Vec<Vec<u8>> = match url.scheme() {
+            "https" => vec![web_transport_quinn::ALPN.as_bytes().to_vec()],
+            "moqt" | "moql" => self
+                .protocols()
+                .into_iter()
+                .map(String::into_bytes)
+                .collect(),
+            _ => unreachable!("checked by check_url"),
+        };
+
+        let mut tls = self.tls.clone();
+        tls.alpn_protocols = alpns;
+        tls.key_log = Arc::new(rustls::KeyLogFile::new());
+        tls.enable_early_data = self.zero_rtt;
+
+        let tls: quinn::crypto::rustls::QuicClientConfig = tls.try_into()?;
+        let mut config = quinn::ClientConfig::new(Arc::new(tls));
+        config.transport_config(self.transport.clone());
+
+        // Pick the initial destination connection ID ourselves, since quinn doesn't
+        // expose it afterwards. Relays key their qlog/mlog traces on it.
+        let mut id = [0; CONNECTION_ID_LEN];
+        self.random
+            .fill(&mut id)
+            .ok()
+            .context("failed to generate connection ID")?;
+        let id = quinn::ConnectionId::new(&id);
+        config.initial_dst_cid_provider(Arc::new(move || id));
+
+        tracing::debug!(%url, %ip, connection_id = %id, "connecting");
+
+        let connecting = self.quic.connect_with(config, ip, &host)?;
+        let handshake = async {
+            match self.zero_rtt {
+                true => match connecting.into_0rtt() {
+                    Ok((quic, accepted)) => anyhow::Ok((quic, Some(accepted.await))),
+                    // No resumable session, or one that doesn't allow early data.
+                    Err(connecting) => Ok((connecting.await?, None)),
+                },
+                false => Ok((connecting.await?, None)),
+            }
+        };
+        let (quic, early_data) = tokio::select! {
+            res = handshake => res?,
+            err = refused(ip) => {
+                return Err(anyhow::Error::new(err).context(format!("{} refused the connection", ip)));
+            }
+        };
+
+        // Only the request carries the token; `url` is what gets logged.
+        let mut request_url = url.clone();
+        let authenticated = match &self.auth_token {
+            Some(token) if url.scheme() == "https" => {
+                request_url.query_pairs_mut().append_pair("jwt", token);
+                true
+            }
+            _ => false,
+        };
+
+        let mut request = web_transport_quinn::proto::ConnectRequest::new(request_url);
+        for protocol in self.protocols() {
+            request = request.with_protocol(protocol);
+        }
+
+        let handshake = quic
+            .handshake_data()
+            .context("missing handshake data")?
+            .downcast::<quinn::crypto::rustls::HandshakeData>()
+            .ok()
+            .context("unexpected handshake data")?;
+
+        let alpn = handshake.protocol.context("missing ALPN")?;
+        let alpn = String::from_utf8(alpn).context("failed to decode ALPN")?;
+
+        let session = match url.scheme() {
+            "https" => web_transport_quinn::Session::connect(quic.clone(), request)
+                .await
+                .map_err(|err| closed_by_peer(err.into(), &quic, &alpn))?,
+            _ => {
+                // Raw QUIC has no CONNECT exchange; the ALPN alone selects the protocol.
+                let response =
+                    web_transport_quinn::proto::ConnectResponse::OK.with_protocol(alpn.clone());
+                web_transport_quinn::Session::raw(quic.clone(), request, response)
+            }
+        };
+
+        Ok(Transport {
+            session,
+            quic,
+            id,
+            alpn,
+            early_data,
+            authenticated,
+        })
+    }
+}
+
+/// A connection ready for MoQ, before any handshake.
+struct Transport {
+    session: web_transport_quinn::Session,
+    quic: quinn::Connection,
+    id: quinn::ConnectionId,
+    alpn: String,
+    early_data: Option<bool>,
+    authenticated: bool,
+}
+
+/// The outcome of `Client::exchange_setup`.
+pub struct SetupExchange {
+    pub server: crate::setup::ServerSetup,
+    id: quinn::ConnectionId,
+    connect_time: Duration,
+    rtt: Duration,
+}
+
+impl SetupExchange {
+    /// The initial destination connection ID, as relays log it.
+    pub fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// How long the QUIC handshake, and WebTransport's CONNECT for `https://`, took.
+    pub fn connect_time(&self) -> Duration {
+        self.connect_time
+    }
+
+    /// The smoothed round-trip time once the relay had answered.
+    pub fn rtt(&self) -> Duration {
+        self.rtt
+    }
+}
+
+/// A MoQ session along with the QUIC connection it runs over.
+#[derive(Clone)]
+pub struct Connection {
+    session: moq_lite::Session,
+    /// Kept for opening streams the MoQ session wouldn't.
+    transport: Arc<web_transport_quinn::Session>,
+    quic: quinn::Connection,
+    id: quinn::ConnectionId,
+    alpn: String,
+    attempts: Option<u32>,
+    connect_time: Duration,
+    early_data: Option<bool>,
+    congestion: Option<Congestion>,
+    impairment: Impairment,
+    authenticated: bool,
+}
+
+impl Connection {
+    /// The protocol version selected during the MoQ handshake.
+    pub fn version(&self) -> moq_lite::Version {
+        self.session.version()
+    }
+
+    /// The original destination connection ID, as hex, for correlating with relay traces.
+    pub fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// How many attempts it took to connect, if retries were enabled.
+    pub fn attempts(&self) -> Option<u32> {
+        self.attempts
+    }
+
+    /// How long `Client::connect` took to hand back the ready session, retries included.
+    pub fn connect_time(&self) -> Duration {
+        self.connect_time
+    }
+
+    /// Whether the relay accepted 0-RTT, if the client attempted it.
+    pub fn early_data(&self) -> Option<bool> {
+        self.early_data
+    }
+
+    /// The congestion controller, if one was picked rather than left to quinn.
+    pub fn congestion(&self) -> Option<Congestion> {
+        self.congestion
+    }
+
+    /// The `--simulate-loss` and `--simulate-jitter-ms` its datagrams went out
+    /// under, if any.
+    pub fn impairment(&self) -> Option<Impairment> {
+        (!self.impairment.is_none()).then_some(self.impairment)
+    }
+
+    /// Whether the session was set up with an auth token.
+    pub fn authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Whether the session runs over WebTransport rather than raw QUIC.
+    pub fn is_webtransport(&self) -> bool {
+        self.alpn == web_transport_quinn::ALPN
+    }
+
+    /// The relay's address, as the QUIC connection sees it.
+    pub fn remote_address(&self) -> SocketAddr {
+        self.quic.remote_address()
+    }
+
+    /// The current smoothed round-trip time estimate.
+    pub fn rtt(&self) -> Duration {
+        self.quic.rtt()
+    }
+
+    /// The code and reason the relay closed the connection with, if it has,
+    /// undoing WebTransport's HTTP/3 encoding of the code.
+    pub fn peer_close(&self) -> Option<(u64, String)> {
+        peer_close(&self.quic, &self.alpn)
+    }
+
+    /// Open a control stream of a type no MoQ version defines, which the relay
+    /// should treat as a protocol violation.
+    pub async fn open_invalid_stream(&self) -> anyhow::Result<()> {
+        let (mut send, _recv) = self
+            .transport
+            .open_bi()
+            .await
+            .context("failed to open stream")?;
+        send.write_all(&[INVALID_STREAM_TYPE])
+            .await
+            .context("failed to write stream type")?;
+        send.finish().context("failed to finish stream")?;
+        Ok(())
+    }
+
+    /// Bytes sent in UDP datagrams so far, QUIC and TLS overhead included.
+    pub fn bytes_sent(&self) -> u64 {
+        self.quic.stats().udp_tx.bytes
+    }
+
+    /// Bytes received in UDP datagrams so far, QUIC and TLS overhead included.
+    pub fn bytes_received(&self) -> u64 {
+        self.quic.stats().udp_rx.bytes
+    }
+
+    /// Whether the QUIC connection has been closed, by either side.
+    pub fn is_closed(&self) -> bool {
+        self.quic.close_reason().is_some()
+    }
+
+    /// Block until the QUIC connection is closed, by either side.
+    pub async fn closed(&self) -> quinn::ConnectionError {
+        self.quic.closed().await
+    }
+
+    pub fn close(&mut self, err: moq_lite::Error) {
+        self.session.close(err);
+    }
+}
+
+/// Marks a handshake failure with the code and reason the relay closed the
+/// connection with, as `Connection::peer_close` reports them.
+#[derive(Debug)]
+pub struct PeerClosed {
+    pub code: u64,
+    pub reason: String,
+}
+
+impl std::fmt::Display for PeerClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reason.is_empty() {
+            true => write!(f, "relay closed the connection with code {}", self.code),
+            false => write!(
+                f,
+                "relay closed the connection with code {}: {}",
+                self.code, self.reason
+            ),
+        }
+    }
+}
+
+/// Attach how the relay closed `quic` to `err`, if it was the one to close it.
+fn closed_by_peer(err: anyhow::Error, quic: &quinn::Connection, alpn: &str) -> anyhow::Error {
+    match peer_close(quic, alpn) {
+        Some((code, reason)) => err.context(PeerClosed { code, reason }),
+        None => err,
+    }
+}
+
+/// Undoes WebTransport's HTTP/3 encoding of the code when `alpn` is WebTransport's.
+fn peer_close(quic: &quinn::Connection, alpn: &str) -> Option<(u64, String)> {
+    let (code, reason) = match quic.close_reason()? {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            let code = close.error_code.into_inner();
+            let code = match alpn == web_transport_quinn::ALPN {
+                true => web_transport_quinn::proto::error_from_http3(code).map_or(code, u64::from),
+                false => code,
+            };
+            (code, close.reason)
+        }
+        quinn::ConnectionError::ConnectionClosed(close) => (close.error_code.into(), close.reason),
+        _ => return None,
+    };
+    Some((code, String::from_utf8_lossy(&reason).into_owned()))
+}
+
+#[derive(Debug)]
+struct NoCertificateVerification(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
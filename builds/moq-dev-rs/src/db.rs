@@ -0,0 +1,134 @@
+//! The `--db` results history, in SQLite.
+//!
+//! Each run appends a row to `runs`, and one to `results` per test report,
+//! with the report's full JSON in `diagnostics` for anything the columns don't
+//! cover:
+//!
+//! ```sql
+//! SELECT test, status, duration_ms, datetime(started_at, 'unixepoch')
+//! FROM results JOIN runs ON runs.id = results.run_id
+//! WHERE relay = 'https://relay.example:4443';
+//! ```
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{Status, TestReport};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    seed INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS results (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    relay TEXT NOT NULL,
+    test TEXT NOT NULL,
+    transport TEXT,
+    status TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    failure_kind TEXT,
+    message TEXT,
+    diagnostics TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS results_test ON results(test);
+";
+
+pub struct Db {
+    conn: rusqlite::Connection,
+}
+
+/// One test's record over the runs `Db::summary` looked at.
+#[derive(Serialize)]
+pub struct TestHistory {
+    pub test: String,
+    pub passed: u64,
+    /// Runs the test wasn't skipped in.
+    pub ran: u64,
+}
+
+impl Db {
+    /// Open the database at `path`, creating it and its tables if need be.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = rusqlite::Connection::open(path)
+            .with_context(|| format!("failed to open database: {}", path.display()))?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| format!("failed to create schema: {}", path.display()))?;
+        Ok(Self { conn })
+    }
+
+    /// Append a run's reports. `relay` is recorded for reports that don't name
+    /// their own, i.e. without `--relay-matrix`.
+    pub fn record(
+        &mut self,
+        started_at: std::time::SystemTime,
+        seed: u64,
+        relay: &str,
+        reports: &[TestReport],
+    ) -> anyhow::Result<()> {
+        let started_at = started_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        let tx = self.conn.transaction()?;
+        // SQLite integers are signed; the seed's bits survive the round trip.
+        tx.execute(
+            "INSERT INTO runs (started_at, seed) VALUES (?1, ?2)",
+            (started_at, seed as i64),
+        )
+        .context("failed to record run")?;
+        let run_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO results (run_id, relay, test, transport, status, duration_ms, failure_kind, message, diagnostics)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for report in reports {
+                let status = match report.status {
+                    Status::Passed => "passed",
+                    Status::Failed => "failed",
+                    Status::Skipped => "skipped",
+                };
+                insert
+                    .execute((
+                        run_id,
+                        report.relay.as_deref().unwrap_or(relay),
+                        report.name,
+                        report.transport,
+                        status,
+                        report.duration_ms as i64,
+                        report.failure_kind.map(|kind| kind.as_str()),
+                        report.message.as_deref(),
+                        serde_json::to_string(report)?,
+                    ))
+                    .with_context(|| format!("failed to record result: {}", report.name))?;
+            }
+        }
+
+        tx.commit().context("failed to commit results")?;
+        Ok(())
+    }
+
+    /// How often each test passed over the last `runs` runs, by test name.
+    pub fn summary(&self, runs: u32) -> anyhow::Result<Vec<TestHistory>> {
+        let mut query = self.conn.prepare(
+            "SELECT test, SUM(status = 'passed'), SUM(status != 'skipped') FROM results
+             WHERE run_id IN (SELECT id FROM runs ORDER BY id DESC LIMIT ?1)
+             GROUP BY test ORDER BY test",
+        )?;
+        let rows = query.query_map([runs], |row| {
+            Ok(TestHistory {
+                test: row.get(0)?,
+                passed: row.get(1)?,
+                ran: row.get(2)?,
+            })
+        })?;
+        rows.collect::<Result<_, _>>()
+            .context("failed to read results")
+    }
+}
@@ -0,0 +1,119 @@
+//! Artificial loss and jitter for `--simulate-loss` and `--simulate-jitter-ms`.
+//!
+//! Only the datagrams we send are impaired; the relay's reach us as the network
+//! delivered them. That still exercises recovery both ways, since the relay
+//! can't tell a lost ack from lost data.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use moq_native::web_transport_quinn::quinn::{self, udp};
+
+/// What to do to outgoing datagrams.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Impairment {
+    /// Percentage of datagrams to drop, from 0 to 100.
+    pub loss: f64,
+    /// The most any datagram is held back; each gets a random delay up to this,
+    /// so they get reordered too.
+    pub jitter: Duration,
+}
+
+impl Impairment {
+    pub fn is_none(&self) -> bool {
+        self.loss == 0.0 && self.jitter.is_zero()
+    }
+
+    /// As reported in diagnostics, e.g. `loss=5%,jitter=20ms`.
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if self.loss > 0.0 {
+            parts.push(format!("loss={}%", self.loss));
+        }
+        if !self.jitter.is_zero() {
+            parts.push(format!("jitter={}ms", self.jitter.as_millis()));
+        }
+        parts.join(",")
+    }
+}
+
+/// A socket that impairs what's sent through it before passing it on.
+#[derive(Debug)]
+pub struct ImpairedSocket {
+    inner: Arc<dyn quinn::AsyncUdpSocket>,
+    impairment: Impairment,
+}
+
+impl ImpairedSocket {
+    pub fn new(inner: Arc<dyn quinn::AsyncUdpSocket>, impairment: Impairment) -> Arc<Self> {
+        Arc::new(Self { inner, impairment })
+    }
+}
+
+impl quinn::AsyncUdpSocket for ImpairedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn quinn::UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &udp::Transmit) -> io::Result<()> {
+        // Dropped datagrams still count as sent, as they would on a lossy link.
+        if fastrand::f64() * 100.0 < self.impairment.loss {
+            return Ok(());
+        }
+
+        if self.impairment.jitter.is_zero() {
+            return self.inner.try_send(transmit);
+        }
+
+        let delay = self.impairment.jitter.mul_f64(fastrand::f64());
+        let inner = self.inner.clone();
+        let (destination, ecn, src_ip) = (transmit.destination, transmit.ecn, transmit.src_ip);
+        let contents = transmit.contents.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let transmit = udp::Transmit {
+                destination,
+                ecn,
+                contents: &contents,
+                segment_size: None,
+                src_ip,
+            };
+            // A socket too busy to take it drops it, like a full queue would.
+            if let Err(err) = inner.try_send(&transmit) {
+                tracing::trace!(%err, "dropped delayed datagram");
+            }
+        });
+
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [udp::RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    // One datagram per transmit, so each is dropped or delayed on its own.
+    fn max_transmit_segments(&self) -> usize {
+        1
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
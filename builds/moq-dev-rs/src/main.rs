@@ -1,336 +1,7084 @@
+use std::io::{IsTerminal, Write};
+use std::num::NonZeroUsize;
 use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use clap::Parser;
-use moq_native::moq_lite;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use moq_lite::*;
+use moq_native::moq_lite;
+use serde::Serialize;
+use tracing::Instrument;
+
+mod config;
+mod connect;
+mod db;
+mod impair;
+mod setup;
 
 #[derive(Parser)]
 #[command(name = "moq-dev-rs-client")]
 #[command(about = "MoQT interop test client using moq-lite/moq-native")]
+#[command(after_long_help = "Exit status:
+  0    every test passed (or was skipped)
+  1    at least one test failed an assertion, or its results couldn't be written
+  2    every failure was a timeout or connection error, mostly timeouts
+  3    every failure was a connection or setup error (refused, TLS, ALPN, auth,
+       version) or a timeout, and timeouts didn't outnumber the rest
+  127  bad command-line usage or configuration
+  130  interrupted (SIGINT); tests that hadn't finished are reported as skipped")]
 struct Cli {
     /// Relay URL (https:// for WebTransport, moqt:// for raw QUIC)
+    ///
+    /// May be repeated or comma-separated; tests that span relays use them in order,
+    /// unless --relay-matrix runs the suite against each one separately.
     #[arg(
         short,
         long,
         env = "RELAY_URL",
+        value_delimiter = ',',
         default_value = "https://localhost:4443"
     )]
-    relay: String,
+    relay: Vec<String>,
+
+    /// Run the whole suite against each --relay in turn, instead of spanning them
+    #[arg(long, env = "RELAY_MATRIX")]
+    relay_matrix: bool,
+
+    /// Which transport to reach the relays over, rewriting the --relay URL schemes
+    #[arg(long, value_enum, default_value_t = Transport::Url)]
+    transport: Transport,
+
+    /// QUIC congestion controller, for comparing throughput between them
+    ///
+    /// Recorded as `congestion` in each test's diagnostics when given.
+    #[arg(long, value_enum)]
+    congestion: Option<connect::Congestion>,
+
+    /// Drop this percentage of the datagrams we send, picked at random
+    ///
+    /// Recorded as `impairment` in each test's diagnostics, along with --simulate-jitter-ms.
+    #[arg(long, value_name = "PCT", default_value = "0", value_parser = parse_percent)]
+    simulate_loss: f64,
+
+    /// Hold each datagram we send back by a random delay of up to this many milliseconds
+    #[arg(long, value_name = "MS", default_value = "0")]
+    simulate_jitter_ms: u64,
 
-    /// Run a specific test case
-    #[arg(short, long, env = "TESTCASE")]
-    test: Option<String>,
+    /// Run only these test cases; may be repeated, and `*`/`?` globs are allowed
+    #[arg(short, long, env = "TESTCASE", value_delimiter = ',')]
+    test: Vec<String>,
+
+    /// Leave these test cases out, after --test and --tag have chosen; may be
+    /// repeated or comma-separated, and `*`/`?` globs are allowed
+    #[arg(long, value_name = "NAME", value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    /// Run only tests with any of these tags; may be repeated or comma-separated
+    #[arg(long, value_delimiter = ',', value_parser = clap::builder::PossibleValuesParser::new(TAGS))]
+    tag: Vec<String>,
 
     /// List available test cases
     #[arg(short, long)]
     list: bool,
 
+    /// List only the tests this client always skips, with the reason for each
+    #[arg(long, conflicts_with = "list")]
+    list_skipped: bool,
+
+    /// Check the relay URLs, TLS files, and test selection, then list what would run without connecting
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Only check that each relay completes a handshake, printing `relay: healthy`
+    #[arg(long, conflicts_with_all = ["list", "dry_run"])]
+    health_check: bool,
+
     /// Disable TLS certificate verification
     #[arg(long, env = "TLS_DISABLE_VERIFY")]
     tls_disable_verify: bool,
 
+    /// Run `tls-verify`, which connects with certificate verification on even
+    /// under --tls-disable-verify; off by default, since local relays often use
+    /// self-signed certificates
+    #[arg(long)]
+    tls_verify_test: bool,
+
+    /// Trust the root certificates in this PEM file as well as the system roots; may be repeated
+    #[arg(long, value_name = "PATH", conflicts_with = "tls_disable_verify")]
+    ca_cert: Vec<std::path::PathBuf>,
+
+    /// Present this PEM certificate chain to the relay (mutual TLS)
+    #[arg(long, value_name = "PATH", requires = "client_key")]
+    client_cert: Option<std::path::PathBuf>,
+
+    /// Private key for --client-cert, in PEM
+    #[arg(long, value_name = "PATH", requires = "client_cert")]
+    client_key: Option<std::path::PathBuf>,
+
+    /// Token to present to relays that require authentication
+    ///
+    /// Sent as the `jwt` query parameter of the WebTransport CONNECT request; raw
+    /// QUIC has nowhere to carry it.
+    #[arg(long, env = "AUTH_TOKEN", value_name = "TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+
+    /// Offer this protocol instead of the MoQ versions' own, for relays expecting a
+    /// nonstandard draft identifier; may be repeated or comma-separated
+    ///
+    /// Sent as the TLS ALPN over raw QUIC, and as the WebTransport subprotocol over https://.
+    #[arg(long, value_name = "PROTOCOL", value_delimiter = ',')]
+    alpn: Vec<String>,
+
+    /// Offer only this MoQ version during setup, for relays that misnegotiate,
+    /// e.g. `moq-lite-02` or `moq-transport-14`
+    #[arg(long, value_name = "VERSION", value_parser = parse_moq_version)]
+    moq_version: Option<moq_lite::Version>,
+
+    /// Local address to send from, as ADDR or ADDR:PORT (`[ADDR]:PORT` for IPv6)
+    ///
+    /// Defaults to every interface on a random port, dual-stack where the host allows.
+    #[arg(long, value_name = "ADDR[:PORT]", value_parser = parse_bind)]
+    bind: Option<std::net::SocketAddr>,
+
     /// Verbose output
     #[arg(short, long, env = "VERBOSE")]
     verbose: bool,
-}
 
-const TESTS: &[&str] = &[
-    "setup-only",
-    "announce-only",
-    "publish-namespace-done",
-    "subscribe-error",
-    "announce-subscribe",
-    "subscribe-before-announce",
-];
+    /// Report a failure's whole error chain too, one cause per line, as `backtrace`
+    #[arg(long)]
+    verbose_errors: bool,
 
-/// Tests that are skipped with a reason.
-/// moq-lite doesn't support subscribing without first receiving an announcement,
-/// so tests that require eager/speculative SUBSCRIBE cannot be implemented.
-const SKIPPED_TESTS: &[(&str, &str)] = &[
-    ("subscribe-error", "moq-lite API requires announcement before subscribe"),
-    ("subscribe-before-announce", "moq-lite API requires announcement before subscribe"),
-];
+    /// Also write logs to this file as JSON, one event per line
+    #[arg(long, value_name = "PATH")]
+    log_json: Option<std::path::PathBuf>,
 
-const TEST_NAMESPACE: &str = "moq-test/interop";
-const TEST_TRACK: &str = "test-track";
+    /// Which log events to record, in `RUST_LOG` syntax
+    #[arg(long, env = "RUST_LOG", default_value = "moq=debug,moq_native=debug")]
+    log_filter: String,
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    /// Namespace the tests publish and subscribe to
+    #[arg(long, default_value = TEST_NAMESPACE)]
+    namespace: String,
 
-    if cli.list {
-        for t in TESTS {
-            println!("{}", t);
-        }
-        return Ok(());
-    }
+    /// Track name the tests publish and subscribe to
+    #[arg(long, default_value = TEST_TRACK)]
+    track: String,
 
-    if cli.verbose {
-        tracing_subscriber::fmt()
-            .with_env_filter("moq=debug,moq_native=debug")
-            .init();
-    }
+    /// Number of tracks `multi-track-announce` publishes on its broadcast
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u8).range(1..))]
+    track_count: u8,
 
-    let tests: Vec<&str> = match &cli.test {
-        Some(name) => {
-            if !TESTS.contains(&name.as_str()) {
-                eprintln!("Unknown test: {}", name);
-                std::process::exit(127);
-            }
-            vec![name.as_str()]
-        }
-        None => TESTS.to_vec(),
-    };
+    /// Stop after this many seconds, failing any tests that haven't finished
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    max_duration: Option<u64>,
 
-    println!("TAP version 14");
-    println!("# moq-dev-rs-client v0.1.0");
-    println!("# Relay: {}", cli.relay);
-    println!("1..{}", tests.len());
+    /// Bytes `throughput-benchmark` sends from publisher to subscriber
+    #[arg(long, default_value = "4194304", value_parser = clap::value_parser!(u64).range(1..))]
+    payload_bytes: u64,
 
-    let relay_url = url::Url::parse(&cli.relay).context("invalid relay URL")?;
+    /// Pause this long between the publisher's announce and the subscriber connecting
+    /// in `announce-subscribe`, to probe how fast the relay propagates announcements
+    #[arg(long, value_name = "MS", default_value = "0")]
+    inject_delay_ms: u64,
 
-    let mut client_config = moq_native::ClientConfig::default();
-    if cli.tls_disable_verify {
-        client_config.tls.disable_verify = Some(true);
-    }
-    let client = client_config.init().context("failed to init client")?;
+    /// How long `idle-session` leaves its session idle, in seconds
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    idle_secs: u64,
 
-    let mut all_passed = true;
+    /// Number of subscriber sessions `fanout` opens
+    #[arg(long, default_value = "2", value_parser = clap::value_parser!(u8).range(1..))]
+    subscribers: u8,
 
-    for (i, test_name) in tests.iter().enumerate() {
-        let num = i + 1;
+    /// How many tracks `max-subscriptions` subscribes to on one session, at most
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u32).range(1..))]
+    max_subscriptions: u32,
 
-        // Check if this test should be skipped
-        if let Some((_, reason)) = SKIPPED_TESTS.iter().find(|(name, _)| name == test_name) {
-            println!("ok {} - {} # SKIP {}", num, test_name, reason);
-            continue;
-        }
+    /// How many times `announce-churn` announces and withdraws its namespace
+    #[arg(long, default_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+    churn_cycles: u32,
 
-        let start = Instant::now();
+    /// How many broadcasts `bulk-announce` publishes on its one session
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u32).range(1..))]
+    announce_count: u32,
 
-        let result = run_test(test_name, &client, &relay_url).await;
-        let duration_ms = start.elapsed().as_millis();
+    /// Size of the single object `large-object` sends, in bytes
+    #[arg(long, default_value = "1048576", value_parser = clap::value_parser!(u64).range(1..))]
+    object_bytes: u64,
 
-        match result {
-            Ok(diag) => {
-                println!("ok {} - {}", num, test_name);
-                print_diagnostics(duration_ms, &diag);
-            }
-            Err(e) => {
-                all_passed = false;
-                println!("not ok {} - {}", num, test_name);
-                print_failure_diagnostics(duration_ms, &format!("{:#}", e));
-            }
+    /// Have `large-object` send this file instead of a generated object, split into
+    /// `--chunk-bytes` objects in one group and reassembled by the subscriber
+    #[arg(long, value_name = "PATH", conflicts_with = "object_bytes")]
+    payload_file: Option<std::path::PathBuf>,
+
+    /// Size of each object `--payload-file` is split into, in bytes
+    #[arg(
+        long,
+        default_value = "65536",
+        requires = "payload_file",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    chunk_bytes: u64,
+
+    /// Seed for generated test data, so a failing run can be repeated exactly
+    ///
+    /// Defaults to a random seed, printed at startup and in the diagnostics of
+    /// every test that generates data from it.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Run the single-session tests over one shared session instead of a fresh one each
+    #[arg(long)]
+    reuse_connection: bool,
+
+    /// Maximum number of tests to run concurrently
+    #[arg(long, default_value = "1")]
+    parallel: NonZeroUsize,
+
+    /// Run each selected test this many times; it passes only if every run passes
+    #[arg(long, default_value = "1")]
+    repeat: NonZeroUsize,
+
+    /// Run each selected test once more first, unreported, so the measured runs
+    /// start with warm TLS session caches and relay state
+    #[arg(long)]
+    warmup: bool,
+
+    /// Stop at the first failing test, ending TAP output with `Bail out!`
+    ///
+    /// Tests still running are cancelled, and those not yet run are reported as skipped.
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Retry failed connection attempts this many times, backing off from 100ms
+    #[arg(long, default_value = "0")]
+    connect_retries: u32,
+
+    /// Re-run a test that timed out up to this many times; other failures are never retried
+    #[arg(long, value_name = "N", default_value = "0")]
+    retry_timeouts: u32,
+
+    /// Multiply every test's timeout by this factor (e.g. for high-latency relays)
+    #[arg(
+        long,
+        default_value = "1.0",
+        allow_hyphen_values = true,
+        value_parser = parse_timeout_scale
+    )]
+    timeout_scale: f64,
+
+    /// Set one test's timeout in milliseconds as NAME=MS, ignoring --timeout-scale
+    #[arg(long, value_name = "NAME=MS", value_parser = parse_timeout_override)]
+    timeout_override: Vec<(String, Duration)>,
+
+    /// Run a shell command before each run of a test, as NAME=CMD; the run fails
+    /// without starting if the command does
+    ///
+    /// Hooks see the test's name in TESTCASE and the relay in RELAY_URL, and their
+    /// output is reported in the test's diagnostics. One still running after 30
+    /// seconds is killed and counts as failed. May be repeated.
+    #[arg(long, value_name = "NAME=CMD", value_parser = parse_hook)]
+    before: Vec<(String, String)>,
+
+    /// Run a shell command after each run of a test, as NAME=CMD, whether it passed or not
+    #[arg(long, value_name = "NAME=CMD", value_parser = parse_hook)]
+    after: Vec<(String, String)>,
+
+    /// Read defaults from this TOML file: per-test timeouts and enabled flags,
+    /// the namespace and track, and connection retries
+    ///
+    /// Flags given on the command line, or through their environment variables, win.
+    #[arg(long, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    /// Output format for test results (and for --list)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Tap)]
+    format: OutputFormat,
+
+    /// Print only the plan and a summary line naming any failures, not each test's result
+    ///
+    /// Applies to TAP and pretty output. TAP consumers will see the plan go unmet.
+    #[arg(long)]
+    summary_only: bool,
+
+    /// Leave out the YAML diagnostics blocks in TAP output, for strict parsers
+    ///
+    /// A failure's message goes in a `#` comment on its `not ok` line instead.
+    #[arg(long)]
+    no_diagnostics: bool,
+
+    /// Also write the results printed on stdout to this file
+    #[arg(long, value_name = "PATH")]
+    output: Option<std::path::PathBuf>,
+
+    /// Also write a JUnit XML report to this file
+    #[arg(long, value_name = "PATH")]
+    junit: Option<std::path::PathBuf>,
+
+    /// Also write a self-contained HTML report to this file
+    #[arg(long, value_name = "PATH")]
+    html: Option<std::path::PathBuf>,
+
+    /// Also write Prometheus metrics to this file, for node_exporter's textfile collector
+    #[arg(long, value_name = "PATH")]
+    metrics: Option<std::path::PathBuf>,
+
+    /// Also append the results to this SQLite database, creating it if need be
+    #[arg(long, value_name = "PATH")]
+    db: Option<std::path::PathBuf>,
+
+    /// Instead of running tests, print each test's pass rate over the last RUNS runs in --db
+    #[arg(
+        long,
+        value_name = "RUNS",
+        num_args = 0..=1,
+        default_missing_value = "10",
+        requires = "db",
+        conflicts_with_all = ["list", "list_skipped", "dry_run", "health_check"]
+    )]
+    db_summary: Option<u32>,
+}
+
+impl Cli {
+    /// Take anything the command line left at its default from `config`.
+    fn overlay(&mut self, config: &config::Config, matches: &clap::ArgMatches) {
+        let defaulted =
+            |id: &str| matches.value_source(id) == Some(clap::parser::ValueSource::DefaultValue);
+
+        if let Some(namespace) = config.namespace.as_ref().filter(|_| defaulted("namespace")) {
+            self.namespace = namespace.clone();
+        }
+        if let Some(track) = config.track.as_ref().filter(|_| defaulted("track")) {
+            self.track = track.clone();
+        }
+        if let Some(retries) = config
+            .connect_retries
+            .filter(|_| defaulted("connect_retries"))
+        {
+            self.connect_retries = retries;
         }
+
+        // Overrides from the file go first, so a --timeout-override for the same test wins.
+        let mut overrides: Vec<(String, Duration)> = config
+            .tests
+            .iter()
+            .filter_map(|(name, test)| {
+                Some((name.clone(), Duration::from_millis(test.timeout_ms?)))
+            })
+            .collect();
+        overrides.append(&mut self.timeout_override);
+        self.timeout_override = overrides;
     }
+}
 
-    if !all_passed {
-        std::process::exit(1);
+fn parse_timeout_scale(s: &str) -> std::result::Result<f64, String> {
+    let scale: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err("must be a positive number".to_string());
     }
+    Ok(scale)
+}
 
-    Ok(())
+fn parse_timeout_override(s: &str) -> std::result::Result<(String, Duration), String> {
+    let (name, ms) = s.split_once('=').ok_or("expected NAME=MS")?;
+    if !TESTS.contains(&name) {
+        return Err(format!("unknown test: {}", name));
+    }
+    let ms: u64 = ms
+        .parse()
+        .map_err(|e| format!("invalid milliseconds: {}", e))?;
+    Ok((name.to_string(), Duration::from_millis(ms)))
 }
 
-#[derive(Default)]
-struct Diagnostics {
-    connection_id: Option<String>,
-    publisher_connection_id: Option<String>,
-    subscriber_connection_id: Option<String>,
+fn parse_percent(s: &str) -> std::result::Result<f64, String> {
+    let pct: f64 = s
+        .parse()
+        .map_err(|_| format!("invalid percentage: {}", s))?;
+    if !(0.0..=100.0).contains(&pct) {
+        return Err(format!("percentage out of range 0-100: {}", s));
+    }
+    Ok(pct)
 }
 
-fn print_diagnostics(duration_ms: u128, diag: &Diagnostics) {
-    println!("  ---");
-    println!("  duration_ms: {}", duration_ms);
-    if let Some(id) = &diag.connection_id {
-        println!("  connection_id: {}", id);
+fn parse_moq_version(s: &str) -> std::result::Result<moq_lite::Version, String> {
+    s.parse()
+        .map_err(|err| format!("{} (expected one of: {})", err, MOQ_VERSIONS.join(", ")))
+}
+
+fn parse_bind(s: &str) -> std::result::Result<std::net::SocketAddr, String> {
+    if let Ok(addr) = s.parse() {
+        return Ok(addr);
     }
-    if let Some(id) = &diag.publisher_connection_id {
-        println!("  publisher_connection_id: {}", id);
+    match s.parse::<std::net::IpAddr>() {
+        Ok(ip) => Ok(std::net::SocketAddr::new(ip, 0)),
+        Err(_) => Err(format!("invalid address: {}", s)),
     }
-    if let Some(id) = &diag.subscriber_connection_id {
-        println!("  subscriber_connection_id: {}", id);
+}
+
+fn parse_hook(s: &str) -> std::result::Result<(String, String), String> {
+    let (name, command) = s.split_once('=').ok_or("expected NAME=CMD")?;
+    if !TESTS.contains(&name) {
+        return Err(format!("unknown test: {}", name));
+    }
+    if command.trim().is_empty() {
+        return Err("empty command".to_string());
     }
-    println!("  ...");
+    Ok((name.to_string(), command.to_string()))
 }
 
-fn print_failure_diagnostics(duration_ms: u128, message: &str) {
-    println!("  ---");
-    println!("  duration_ms: {}", duration_ms);
-    println!("  message: \"{}\"", message.replace('"', "\\\""));
-    println!("  ...");
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
 }
 
-async fn run_test(
-    name: &str,
-    client: &moq_native::Client,
-    relay_url: &url::Url,
-) -> anyhow::Result<Diagnostics> {
-    let timeout = match name {
-        "setup-only" => Duration::from_secs(2),
-        "announce-only" => Duration::from_secs(2),
-        "publish-namespace-done" => Duration::from_secs(2),
-        "announce-subscribe" => Duration::from_secs(3),
-        _ => Duration::from_secs(5),
-    };
+/// Match a test name against a shell-style glob, where `*` matches any run of
+/// characters and `?` matches exactly one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
 
-    tokio::time::timeout(timeout, run_test_inner(name, client, relay_url))
-        .await
-        .context(format!("timeout after {}ms", timeout.as_millis()))?
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the most recent `*`, if the rest fails to match.
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
-async fn run_test_inner(
-    name: &str,
-    client: &moq_native::Client,
-    relay_url: &url::Url,
-) -> anyhow::Result<Diagnostics> {
-    match name {
-        "setup-only" => test_setup_only(client, relay_url).await,
-        "announce-only" => test_announce_only(client, relay_url).await,
-        "publish-namespace-done" => test_publish_namespace_done(client, relay_url).await,
-        "announce-subscribe" => test_announce_subscribe(client, relay_url).await,
-        _ => anyhow::bail!("unknown test: {}", name),
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// TAP version 14 on stdout
+    Tap,
+    /// A JSON array with one object per test, followed by a summary object
+    Json,
+    /// One JSON object per line, printed as each test finishes, then a summary line
+    Jsonl,
+    /// A table of results for people, colorized on a terminal unless NO_COLOR is set
+    Pretty,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Transport {
+    /// Whichever each --relay URL's scheme says
+    Url,
+    /// WebTransport (https://)
+    Webtransport,
+    /// Raw QUIC (moqt://)
+    RawQuic,
+    /// Run the suite over WebTransport, then again over raw QUIC, on the same host and port
+    Both,
+}
+
+impl Transport {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Url => "url",
+            Self::Webtransport => "webtransport",
+            Self::RawQuic => "raw-quic",
+            Self::Both => "both",
+        }
+    }
+
+    /// The same relay addressed over this transport.
+    fn rewrite(self, url: &url::Url) -> anyhow::Result<url::Url> {
+        match self {
+            Self::Url | Self::Both => Ok(url.clone()),
+            Self::Webtransport => connect::webtransport_url(url),
+            Self::RawQuic => connect::raw_quic_url(url),
+        }
     }
 }
 
-/// Connect via WebTransport, complete handshake, close session.
-async fn test_setup_only(
-    client: &moq_native::Client,
-    relay_url: &url::Url,
-) -> anyhow::Result<Diagnostics> {
-    let session = client
-        .clone()
-        .connect(relay_url.clone())
-        .await
-        .context("failed to connect")?;
-    session.close(moq_lite::Error::Cancel);
+/// One pass of the suite: the relays its tests use, and what to label its results with.
+struct Target {
+    relay_urls: Vec<url::Url>,
+    /// The relay, with `--relay-matrix`.
+    relay: Option<String>,
+    /// The transport, with `--transport both`.
+    transport: Option<Transport>,
+}
 
-    Ok(Diagnostics::default())
+impl Target {
+    /// The TAP comment heading this target's block of results, if it needs one.
+    fn heading(&self) -> Option<String> {
+        match (&self.relay, self.transport) {
+            (Some(relay), Some(transport)) => Some(format!(
+                "# Testing relay: {} over {}",
+                relay,
+                transport.label()
+            )),
+            (Some(relay), None) => Some(format!("# Testing relay: {}", relay)),
+            (None, Some(transport)) => Some(format!("# Testing transport: {}", transport.label())),
+            (None, None) => None,
+        }
+    }
 }
 
-/// Connect, publish broadcast at test namespace, wait for acknowledgment.
-async fn test_announce_only(
-    client: &moq_native::Client,
-    relay_url: &url::Url,
-) -> anyhow::Result<Diagnostics> {
-    let origin = Origin::produce();
+const TESTS: &[&str] = &[
+    "setup-only",
+    "setup-only-rawquic",
+    "announce-only",
+    "publish-namespace-done",
+    "subscribe-error",
+    "announce-subscribe",
+    "subscribe-before-announce",
+    "subscribe-then-unannounce",
+    "data-roundtrip",
+    "cross-relay-subscribe",
+    "goaway-handling",
+    "multi-track-announce",
+    "priority-ordering",
+    "fetch-past-objects",
+    "subscribe-missing-track",
+    "throughput-benchmark",
+    "reconnect",
+    "close-codes",
+    "subscribe-update",
+    "re-announce",
+    "large-object",
+    "fanout",
+    "idle-session",
+    "prefix-announce",
+    "group-ordering",
+    "subscribe-before-track",
+    "duplicate-subscribe",
+    "malformed-namespace",
+    "subscribe-range",
+    "namespace-conflict",
+    "resubscribe",
+    "max-subscriptions",
+    "setup-only-ipv6",
+    "time-to-first-object",
+    "zero-rtt-resume",
+    "relay-initiated-close",
+    "backlog-announce",
+    "subscribe-abort",
+    "authenticated-announce",
+    "announce-churn",
+    "impaired-delivery",
+    "object-boundary",
+    "interleaved-tracks",
+    "bulk-announce",
+    "tls-verify",
+    "priority-delivery",
+    "live-stream",
+    "binary-names",
+    "backpressure",
+    "setup-params",
+];
 
-    // Create broadcast before connecting
-    let broadcast = Broadcast::produce();
-    origin.publish_broadcast(TEST_NAMESPACE, broadcast.consume());
+/// Tests that are skipped with a reason.
+/// moq-lite doesn't support subscribing without first receiving an announcement,
+/// so tests that require eager/speculative SUBSCRIBE cannot be implemented.
+/// Nor does it expose FETCH, only live subscriptions.
+const SKIPPED_TESTS: &[(&str, &str)] = &[
+    (
+        "subscribe-error",
+        "moq-lite API requires announcement before subscribe",
+    ),
+    (
+        "subscribe-before-announce",
+        "moq-lite API requires announcement before subscribe",
+    ),
+    ("fetch-past-objects", "moq-lite API does not expose FETCH"),
+    // moq-lite has a SUBSCRIBE_UPDATE message but never sends one; this needs
+    // something like `TrackConsumer::set_priority` to change it mid-subscription.
+    (
+        "subscribe-update",
+        "moq-lite API cannot update an active subscription",
+    ),
+    // The lite SUBSCRIBE carries start and end groups, but the subscriber always
+    // sends `None`; `BroadcastConsumer::subscribe_track` only takes a `Track`.
+    (
+        "subscribe-range",
+        "moq-lite API has no subscribe_track variant taking a group range",
+    ),
+];
 
-    let session = client
-        .clone()
-        .with_publish(origin.consume())
-        .connect(relay_url.clone())
-        .await
-        .context("failed to connect")?;
+/// Categories for `--tag`.
+const TAGS: &[&str] = &["setup", "announce", "subscribe", "data", "error"];
 
-    // Wait briefly for the announce to be processed
-    tokio::time::sleep(Duration::from_millis(500)).await;
+/// Versions `--moq-version` can pin, as moq-lite names them.
+const MOQ_VERSIONS: &[&str] = &[
+    "moq-lite-01",
+    "moq-lite-02",
+    "moq-lite-03",
+    "moq-transport-14",
+    "moq-transport-15",
+    "moq-transport-16",
+    "moq-transport-17",
+];
 
-    session.close(moq_lite::Error::Cancel);
+/// The `TAGS` each test belongs to.
+const TEST_TAGS: &[(&str, &[&str])] = &[
+    ("setup-only", &["setup"]),
+    ("setup-only-rawquic", &["setup"]),
+    ("announce-only", &["announce"]),
+    ("publish-namespace-done", &["announce"]),
+    ("subscribe-error", &["subscribe", "error"]),
+    ("announce-subscribe", &["announce", "subscribe"]),
+    ("subscribe-before-announce", &["subscribe"]),
+    ("subscribe-then-unannounce", &["announce", "subscribe"]),
+    ("data-roundtrip", &["data"]),
+    ("cross-relay-subscribe", &["announce", "subscribe"]),
+    ("goaway-handling", &["setup"]),
+    ("multi-track-announce", &["announce", "subscribe"]),
+    ("priority-ordering", &["data"]),
+    ("fetch-past-objects", &["data"]),
+    ("subscribe-missing-track", &["subscribe", "error"]),
+    ("throughput-benchmark", &["data"]),
+    ("reconnect", &["setup"]),
+    ("close-codes", &["setup", "error"]),
+    ("subscribe-update", &["subscribe"]),
+    ("re-announce", &["announce"]),
+    ("large-object", &["data"]),
+    ("fanout", &["subscribe", "data"]),
+    ("idle-session", &["setup", "announce"]),
+    ("prefix-announce", &["announce"]),
+    ("group-ordering", &["data"]),
+    ("subscribe-before-track", &["subscribe", "data"]),
+    ("duplicate-subscribe", &["subscribe"]),
+    ("malformed-namespace", &["announce", "error"]),
+    ("subscribe-range", &["subscribe", "data"]),
+    ("namespace-conflict", &["announce", "subscribe"]),
+    ("resubscribe", &["subscribe", "data"]),
+    ("max-subscriptions", &["subscribe"]),
+    ("setup-only-ipv6", &["setup"]),
+    ("time-to-first-object", &["subscribe", "data"]),
+    ("zero-rtt-resume", &["setup"]),
+    ("relay-initiated-close", &["setup", "error"]),
+    ("backlog-announce", &["announce"]),
+    ("subscribe-abort", &["subscribe", "data"]),
+    ("authenticated-announce", &["setup", "announce"]),
+    ("announce-churn", &["announce"]),
+    ("impaired-delivery", &["data"]),
+    ("object-boundary", &["data"]),
+    ("interleaved-tracks", &["data"]),
+    ("bulk-announce", &["announce"]),
+    ("tls-verify", &["setup"]),
+    ("priority-delivery", &["data"]),
+    ("live-stream", &["data"]),
+    ("binary-names", &["announce", "subscribe"]),
+    ("backpressure", &["data"]),
+    ("setup-params", &["setup"]),
+];
 
-    Ok(Diagnostics::default())
+fn tags(name: &str) -> &'static [&'static str] {
+    TEST_TAGS
+        .iter()
+        .find(|(test, _)| *test == name)
+        .map_or(&[], |(_, tags)| tags)
 }
 
-/// Connect, publish broadcast, then close/drop the broadcast.
-async fn test_publish_namespace_done(
-    client: &moq_native::Client,
-    relay_url: &url::Url,
-) -> anyhow::Result<Diagnostics> {
-    let origin = Origin::produce();
+/// Tests that need more than one `--relay`, skipped when only one is given.
+const MULTI_RELAY_TESTS: &[(&str, &str)] = &[(
+    "cross-relay-subscribe",
+    "requires two relay URLs (pass --relay twice)",
+)];
 
-    let broadcast = Broadcast::produce();
-    origin.publish_broadcast(TEST_NAMESPACE, broadcast.consume());
+/// Tests that need an IPv6 relay address, skipped for any other.
+const IPV6_TESTS: &[(&str, &str)] = &[(
+    "setup-only-ipv6",
+    "requires an IPv6 relay address (e.g. --relay https://[::1]:4443)",
+)];
 
-    let session = client
-        .clone()
-        .with_publish(origin.consume())
-        .connect(relay_url.clone())
-        .await
-        .context("failed to connect")?;
+/// Tests that only run when asked for with `--tls-verify-test`.
+const TLS_VERIFY_TESTS: &[(&str, &str)] = &[(
+    "tls-verify",
+    "requires --tls-verify-test and a relay with a trusted certificate",
+)];
 
-    // Wait for announce to be processed, then drop the broadcast (unpublish)
-    tokio::time::sleep(Duration::from_millis(500)).await;
-    drop(broadcast);
+/// Tests that send `--auth-token`, which raw QUIC has no request to carry in;
+/// skipped for a raw QUIC relay when a token is given.
+const AUTH_TOKEN_TESTS: &[(&str, &str)] = &[(
+    "authenticated-announce",
+    "raw QUIC can't carry --auth-token; use an https:// relay",
+)];
 
-    // Wait briefly for the done to propagate
-    tokio::time::sleep(Duration::from_millis(200)).await;
+/// Tests that only need one session and leave it usable, so they can share one
+/// with `--reuse-connection`.
+const REUSABLE_TESTS: &[&str] = &["setup-only", "announce-only", "publish-namespace-done"];
 
-    session.close(moq_lite::Error::Cancel);
+/// How long `announce-subscribe`'s subscriber waits for the announcement
+/// before deciding the relay isn't forwarding it.
+const ANNOUNCE_WINDOW: Duration = Duration::from_millis(1500);
 
-    Ok(Diagnostics::default())
-}
+/// How long `goaway-handling` holds the session open waiting for a GOAWAY.
+const GOAWAY_WINDOW: Duration = Duration::from_secs(3);
 
-/// Two connections: publisher announces, subscriber subscribes.
-async fn test_announce_subscribe(
-    client: &moq_native::Client,
-    relay_url: &url::Url,
-) -> anyhow::Result<Diagnostics> {
-    // Publisher setup
-    let pub_origin = Origin::produce();
-    let mut broadcast = Broadcast::produce();
-    pub_origin.publish_broadcast(TEST_NAMESPACE, broadcast.consume());
+/// Names for the first tracks in `multi-track-announce`; any beyond these are numbered.
+const MULTI_TRACK_NAMES: &[&str] = &["audio", "video", "captions"];
 
-    // Create a track so subscriber can find it
-    let _track = broadcast.create_track(Track {
-        name: TEST_TRACK.to_string(),
-        priority: 0,
-    });
+/// Groups `priority-ordering` and `priority-delivery` flood onto each of their two tracks.
+const PRIORITY_GROUPS: usize = 16;
+/// Frames per group, and bytes per frame, in both: enough to
+/// back up the connection so the relay has to choose what to send first.
+const PRIORITY_FRAMES: usize = 4;
+const PRIORITY_FRAME_SIZE: usize = 64 * 1024;
+/// How much slower, on average, `priority-delivery` lets its high-priority
+/// groups arrive than its low-priority ones before failing, as a fraction of
+/// the low-priority average; loopback rarely congests enough for a clear lead.
+const PRIORITY_DELIVERY_TOLERANCE: f64 = 0.5;
 
-    let pub_session = client
-        .clone()
-        .with_publish(pub_origin.consume())
-        .connect(relay_url.clone())
-        .await
-        .context("publisher failed to connect")?;
+/// Track `subscribe-missing-track` subscribes to, which the publisher never creates.
+const MISSING_TRACK: &str = "does-not-exist";
 
-    // Give the relay time to process the announce
-    tokio::time::sleep(Duration::from_millis(300)).await;
+/// How long `re-announce` waits for the first announcement to be withdrawn
+/// before publishing the namespace again anyway.
+const UNANNOUNCE_WINDOW: Duration = Duration::from_secs(2);
 
-    // Subscriber setup
-    let sub_origin = Origin::produce();
-    let mut sub_consumer = sub_origin.consume();
+/// How long `subscribe-before-track` leaves the subscription waiting before
+/// the publisher creates the track.
+const LATE_TRACK_DELAY: Duration = Duration::from_millis(500);
 
-    let sub_session = client
-        .clone()
-        .with_consume(sub_origin)
-        .connect(relay_url.clone())
-        .await
-        .context("subscriber failed to connect")?;
+/// The path segment `malformed-namespace` announces under the test namespace:
+/// control characters, then enough filler to dwarf any sane namespace.
+const MALFORMED_PREFIX: &str = "\0\x1b[31m\x7f";
+const MALFORMED_FILLER_BYTES: usize = 64 * 1024;
 
-    // Wait for the relay to announce the published broadcast
-    let sub_broadcast = tokio::select! {
-        announced = sub_consumer.announced() => {
-            match announced.context("consumer closed")? {
-                (_, Some(broadcast)) => broadcast,
-                (path, None) => anyhow::bail!("unexpected unannouncement: {}", path),
-            }
-        }
-        _ = tokio::time::sleep(Duration::from_millis(1500)) => {
-            anyhow::bail!("timeout waiting for announcement");
-        }
-    };
+/// How long `malformed-namespace` waits to see whether the relay closes the session.
+const MALFORMED_WINDOW: Duration = Duration::from_secs(1);
 
-    // Now subscribe to a track on the announced broadcast
-    let track = sub_broadcast.subscribe_track(&Track {
-        name: TEST_TRACK.to_string(),
-        priority: 0,
-    });
+/// The path segment `binary-names` announces under `binary-names/` in the test
+/// namespace, and the suffix of its track name: accented, CJK, and astral
+/// characters, then a C1 control and U+00FF, so every byte but the separators
+/// is 0x80 or above.
+const BINARY_NAMESPACE: &str = "ünïcødé-日本語-🎬-\u{80}\u{ff}";
+const BINARY_TRACK: &str = "tråck-ß-🎵-\u{fffd}";
 
-    // Wait for the track subscription to be acknowledged
-    tokio::select! {
-        result = track.closed() => {
-            result.context("track closed")?;
-        }
-        _ = tokio::time::sleep(Duration::from_millis(1000)) => {
-            // Timeout waiting - subscription was accepted (no error)
-        }
-    }
+/// How long `binary-names`' subscriber waits for an announcement under the test namespace.
+const BINARY_NAMES_WINDOW: Duration = Duration::from_secs(1);
 
-    pub_session.close(moq_lite::Error::Cancel);
-    sub_session.close(moq_lite::Error::Cancel);
+/// How long `relay-initiated-close` waits for the relay to close the session
+/// after it opens an invalid stream.
+const RELAY_CLOSE_WINDOW: Duration = Duration::from_secs(2);
+
+/// How long `namespace-conflict` keeps reading after the first group, for data
+/// from the other publisher.
+const CONFLICT_WINDOW: Duration = Duration::from_millis(500);
+
+/// Frames in each group a `namespace-conflict` publisher writes.
+const CONFLICT_GROUP_FRAMES: usize = 3;
+
+/// How long `resubscribe` leaves the track unsubscribed before subscribing again.
+const RESUBSCRIBE_GAP: Duration = Duration::from_millis(200);
+
+/// How long `subscribe-abort` gives the relay to clean up after the first
+/// subscriber's session closes before the second one connects.
+const SUBSCRIBE_ABORT_GAP: Duration = Duration::from_millis(200);
+
+/// How long `resubscribe` waits for the second subscription to reach the
+/// publisher before concluding the relay kept the first one open upstream.
+const RESUBSCRIBE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Bytes of `--before`/`--after` hook output kept for the diagnostics.
+const HOOK_OUTPUT_LIMIT: usize = 4096;
+
+/// How long a `--before`/`--after` hook may run before it's killed and counted
+/// as failed. Hooks run outside the test's own timeout.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `max-subscriptions` waits for each subscription's data before
+/// concluding the relay has stalled.
+const SUBSCRIPTION_STALL: Duration = Duration::from_secs(1);
+
+/// How often the tests with a live track publish a group, bounding how long a
+/// relay that only forwards new groups makes a subscriber wait.
+const LIVE_GROUP_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How many groups `group-ordering` publishes back to back.
+const GROUP_ORDERING_GROUPS: u64 = 8;
+
+/// The sub-namespace `prefix-announce` scopes its subscriber to.
+const PREFIX_ANNOUNCE_SCOPE: &str = "prefix-announce";
+
+/// How many broadcasts `backlog-announce` has announced before the subscriber joins.
+const BACKLOG_BROADCASTS: usize = 3;
+
+/// How long `backlog-announce` gives the relay to learn the announcements
+/// before the subscriber connects.
+const BACKLOG_DELAY: Duration = Duration::from_millis(200);
+
+/// How long `backlog-announce`'s subscriber waits to discover them all.
+const BACKLOG_WINDOW: Duration = Duration::from_secs(1);
+
+/// How long `announce-churn` keeps its namespace announced, and then withdrawn,
+/// in each cycle.
+const CHURN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long `announce-churn`'s subscriber must go without an announcement
+/// before it considers the churn to have settled.
+const CHURN_QUIET: Duration = Duration::from_millis(300);
+
+/// How many one-object groups `impaired-delivery` publishes, one every
+/// `LIVE_GROUP_INTERVAL`.
+const IMPAIRED_OBJECTS: u64 = 50;
+
+/// How long `impaired-delivery`'s subscriber waits for another object before
+/// counting the rest as lost.
+const IMPAIRED_WINDOW: Duration = Duration::from_secs(2);
+
+/// How many groups `interleaved-tracks` writes to each of its two tracks, and
+/// how many objects to a group.
+const INTERLEAVED_GROUPS: u64 = 10;
+const INTERLEAVED_OBJECTS: u64 = 3;
+
+/// Body sizes of the objects `object-boundary` sends, in order: empty and tiny
+/// ones, ones either side of a typical datagram, and ones spanning many frames.
+const BOUNDARY_SIZES: &[usize] = &[0, 1, 3, 100, 1199, 1200, 1201, 16383, 16384, 65536, 200_000];
+
+/// Length of the big-endian body length `object-boundary` puts in front of each object.
+const BOUNDARY_PREFIX_LEN: usize = 4;
+
+/// How many undiscovered broadcasts a failure names before summarizing the rest.
+const MISSING_LISTED: usize = 10;
+
+/// How long `bulk-announce`'s subscriber waits to discover every broadcast.
+const BULK_ANNOUNCE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long `live-stream`'s subscriber follows the stream once subscribed.
+const LIVE_STREAM_DURATION: Duration = Duration::from_secs(3);
+
+/// Groups `backpressure` writes all at once, one object of
+/// `BACKPRESSURE_OBJECT_SIZE` bytes each, and how long its subscriber takes
+/// over reading each one: about 1.3 seconds to drain 4 MiB.
+const BACKPRESSURE_GROUPS: u64 = 64;
+const BACKPRESSURE_OBJECT_SIZE: usize = 64 * 1024;
+const BACKPRESSURE_READ_DELAY: Duration = Duration::from_millis(20);
+
+/// The max request ID `setup-params` offers, the same as moq-lite's.
+const SETUP_MAX_REQUEST_ID: u64 = u32::MAX as u64;
+
+/// How long `fanout` gives every subscriber, together, to receive the group.
+const FANOUT_WINDOW: Duration = Duration::from_secs(2);
+
+/// Errors `close-codes` closes its sessions with, one session each.
+const CLOSE_ERRORS: &[moq_lite::Error] = &[
+    moq_lite::Error::Cancel,
+    moq_lite::Error::Timeout,
+    moq_lite::Error::NotFound,
+    moq_lite::Error::Unauthorized,
+    moq_lite::Error::ProtocolViolation,
+    moq_lite::Error::App(1),
+];
+
+/// How `throughput-benchmark` splits its payload: frames of this many bytes,
+/// this many frames to a group.
+const THROUGHPUT_FRAME_SIZE: usize = 16 * 1024;
+const THROUGHPUT_GROUP_FRAMES: usize = 16;
+
+const TEST_NAMESPACE: &str = "moq-test/interop";
+const TEST_TRACK: &str = "test-track";
 
-    Ok(Diagnostics::default())
+/// Frames written by the publisher in `data-roundtrip` (after its `SEND_STAMP_LEN`
+/// timestamp), in order, as a single group.
+const TEST_FRAMES: &[&[u8]] = &[
+    b"moq-interop frame 0",
+    &[0x00, 0x01, 0x7f, 0x80, 0xfe, 0xff],
+    b"moq-interop frame 2",
+];
+
+/// Length of the send timestamp `data-roundtrip` puts in front of each frame:
+/// big-endian microseconds since the test started.
+const SEND_STAMP_LEN: usize = 8;
+
+/// Install the human-readable logger for `--verbose` and the JSON one for `--log-json`.
+fn init_logging(cli: &Cli) -> anyhow::Result<()> {
+    use tracing_subscriber::prelude::*;
+
+    if !cli.verbose && cli.log_json.is_none() {
+        return Ok(());
+    }
+
+    let filter = tracing_subscriber::EnvFilter::try_new(&cli.log_filter)
+        .with_context(|| format!("invalid log filter: {}", cli.log_filter))?;
+
+    let json = match &cli.log_json {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("failed to create log file: {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(std::sync::Mutex::new(file));
+            Some(layer)
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(cli.verbose.then(tracing_subscriber::fmt::layer))
+        .with(json)
+        .init();
+
+    Ok(())
+}
+
+/// Process exit codes, as listed in `--help`.
+const EXIT_PASSED: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+const EXIT_TIMEOUT: i32 = 2;
+const EXIT_CONNECT: i32 = 3;
+const EXIT_USAGE: i32 = 127;
+const EXIT_INTERRUPTED: i32 = 130;
+
+#[tokio::main]
+async fn main() {
+    // Parse by hand to keep the matches, which say which flags were actually given.
+    let parsed = Cli::command()
+        .try_get_matches()
+        .and_then(|matches| Ok((Cli::from_arg_matches(&matches)?, matches)));
+    let (cli, matches) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            // --help and --version "fail" too, onto stdout.
+            let code = match err.use_stderr() {
+                true => EXIT_USAGE,
+                false => EXIT_PASSED,
+            };
+            let _ = err.print();
+            std::process::exit(code);
+        }
+    };
+
+    // Test failures are reported, not returned, so an error here means the
+    // suite couldn't run as asked: a bad URL, an unreadable TLS file, and so on.
+    let code = match run(cli, &matches).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            EXIT_USAGE
+        }
+    };
+    std::process::exit(code);
+}
+
+async fn run(mut cli: Cli, matches: &clap::ArgMatches) -> anyhow::Result<i32> {
+    let config = match &cli.config {
+        Some(path) => config::Config::load(path, TESTS)?,
+        None => config::Config::default(),
+    };
+    cli.overlay(&config, matches);
+
+    let timeouts = Timeouts {
+        scale: cli.timeout_scale,
+        overrides: cli.timeout_override.clone(),
+        idle: Duration::from_secs(cli.idle_secs),
+        inject_delay: Duration::from_millis(cli.inject_delay_ms),
+    };
+
+    let tagged = |name: &&str| {
+        cli.tag.is_empty()
+            || tags(name)
+                .iter()
+                .any(|t| cli.tag.iter().any(|tag| tag == t))
+    };
+    let included = |name: &&str| !cli.exclude.iter().any(|pattern| glob_match(pattern, name));
+    let enabled = |name: &&str| !config.disabled().any(|disabled| disabled == *name);
+
+    if cli.list {
+        // What a run without --test would run.
+        let tests: Vec<&'static str> = TESTS
+            .iter()
+            .copied()
+            .filter(enabled)
+            .filter(tagged)
+            .filter(included)
+            .collect();
+        match cli.format {
+            // Plain identifiers are what harnesses parse, so tags only with --verbose.
+            OutputFormat::Tap | OutputFormat::Pretty => {
+                for t in tests {
+                    match cli.verbose {
+                        true => println!("{} [{}]", t, tags(t).join(", ")),
+                        false => println!("{}", t),
+                    }
+                }
+            }
+            format => {
+                // The URLs haven't been validated yet; one that doesn't parse isn't IPv6.
+                let relay = cli
+                    .relay
+                    .first()
+                    .and_then(|relay| url::Url::parse(relay).ok());
+                let ipv6 = relay.as_ref().is_some_and(connect::is_ipv6);
+                let tls_verify = cli.tls_verify_test;
+                let raw_auth = cli.auth_token.is_some()
+                    && relay.as_ref().is_some_and(|url| url.scheme() != "https");
+                let relays = cli.relay.len();
+                print_listing(
+                    &tests, relays, ipv6, tls_verify, raw_auth, &timeouts, format,
+                )?
+            }
+        }
+        return Ok(EXIT_PASSED);
+    }
+
+    if cli.list_skipped {
+        let skipped: Vec<SkippedListing> = SKIPPED_TESTS
+            .iter()
+            .filter(|(name, _)| tagged(name) && included(name))
+            .map(|&(name, reason)| SkippedListing { name, reason })
+            .collect();
+        match cli.format {
+            OutputFormat::Tap | OutputFormat::Pretty => {
+                let width = skipped.iter().map(|s| s.name.len()).max().unwrap_or(0);
+                for s in &skipped {
+                    println!("{:width$}  {}", s.name, s.reason);
+                }
+            }
+            OutputFormat::Jsonl => {
+                for s in &skipped {
+                    println!("{}", serde_json::to_string(s)?);
+                }
+            }
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&skipped)?),
+        }
+        return Ok(EXIT_PASSED);
+    }
+
+    if let (Some(runs), Some(path)) = (cli.db_summary, &cli.db) {
+        let history = db::Db::open(path)?.summary(runs)?;
+        match cli.format {
+            OutputFormat::Tap | OutputFormat::Pretty => {
+                let width = history.iter().map(|h| h.test.len()).max().unwrap_or(0);
+                for h in &history {
+                    let rate = match h.ran {
+                        0 => "skipped".to_string(),
+                        ran => format!("{:.0}%", h.passed as f64 * 100.0 / ran as f64),
+                    };
+                    println!("{:width$}  {}/{}  {}", h.test, h.passed, h.ran, rate);
+                }
+            }
+            OutputFormat::Jsonl => {
+                for h in &history {
+                    println!("{}", serde_json::to_string(h)?);
+                }
+            }
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&history)?),
+        }
+        return Ok(EXIT_PASSED);
+    }
+
+    init_logging(&cli)?;
+
+    let tests: Vec<&'static str> = match cli.test.is_empty() {
+        // Naming a test with --test runs it even if the config disables it.
+        true => TESTS.iter().copied().filter(enabled).collect(),
+        false => {
+            for pattern in &cli.test {
+                if !TESTS.iter().any(|t| glob_match(pattern, t)) {
+                    match is_glob(pattern) {
+                        true => eprintln!("No tests match pattern: {}", pattern),
+                        false => eprintln!("Unknown test: {}", pattern),
+                    }
+                    return Ok(EXIT_USAGE);
+                }
+            }
+
+            // Keep suite order (and drop duplicates) regardless of how tests were selected.
+            TESTS
+                .iter()
+                .copied()
+                .filter(|t| cli.test.iter().any(|pattern| glob_match(pattern, t)))
+                .collect()
+        }
+    };
+    if tests.is_empty() {
+        eprintln!("Every test is disabled by --config");
+        return Ok(EXIT_USAGE);
+    }
+    let tests: Vec<&'static str> = tests.into_iter().filter(tagged).collect();
+    if tests.is_empty() {
+        eprintln!("No selected tests have tag: {}", cli.tag.join(", "));
+        return Ok(EXIT_USAGE);
+    }
+
+    // A stale exclusion isn't worth failing the run over.
+    for pattern in &cli.exclude {
+        if !TESTS.iter().any(|t| glob_match(pattern, t)) {
+            match is_glob(pattern) {
+                true => eprintln!("Warning: no tests match excluded pattern: {}", pattern),
+                false => eprintln!("Warning: excluding unknown test: {}", pattern),
+            }
+        }
+    }
+    let tests: Vec<&'static str> = tests.into_iter().filter(included).collect();
+    if tests.is_empty() {
+        eprintln!("Every selected test is excluded");
+        return Ok(EXIT_USAGE);
+    }
+
+    // Validate relay URLs before printing anything, so a typo fails fast and clearly.
+    let mut relay_urls = Vec::with_capacity(cli.relay.len());
+    for relay in &cli.relay {
+        let url =
+            url::Url::parse(relay).with_context(|| format!("invalid relay URL: {}", relay))?;
+        connect::check_url(&url)?;
+        relay_urls.push(url);
+    }
+
+    let payload = match &cli.payload_file {
+        Some(path) => {
+            let payload = std::fs::read(path)
+                .with_context(|| format!("failed to read payload: {}", path.display()))?;
+            anyhow::ensure!(!payload.is_empty(), "payload is empty: {}", path.display());
+            Some(bytes::Bytes::from(payload))
+        }
+        None => None,
+    };
+
+    let mut client_config = moq_native::ClientConfig::default();
+    if cli.tls_disable_verify {
+        client_config.tls.disable_verify = Some(true);
+    }
+    client_config.tls.root = cli.ca_cert;
+    client_config.version = cli.moq_version.into_iter().collect();
+    if let Some(bind) = cli.bind {
+        client_config.bind = bind;
+    }
+    let identity = match (cli.client_cert, cli.client_key) {
+        (Some(cert), Some(key)) => Some(connect::ClientIdentity { cert, key }),
+        _ => None,
+    };
+    let impairment = impair::Impairment {
+        loss: cli.simulate_loss,
+        jitter: Duration::from_millis(cli.simulate_jitter_ms),
+    };
+    let client = connect::Client::new(
+        &client_config,
+        identity.as_ref(),
+        cli.congestion,
+        impairment,
+    )
+    .context("failed to init client")?
+    .with_retries(cli.connect_retries)
+    .with_alpns(cli.alpn.clone())
+    .with_auth_token(cli.auth_token.clone());
+
+    // `tls-verify` needs a client that checks certificates whatever the rest
+    // use, on its own port in case --bind fixed one.
+    let verifying_client = match cli.tls_verify_test {
+        true => {
+            let mut config = client_config.clone();
+            config.tls.disable_verify = None;
+            config.bind.set_port(0);
+            let client =
+                connect::Client::new(&config, identity.as_ref(), cli.congestion, impairment)
+                    .context("failed to init verifying client")?
+                    .with_retries(cli.connect_retries)
+                    .with_alpns(cli.alpn.clone())
+                    .with_auth_token(cli.auth_token.clone());
+            Some(client)
+        }
+        false => None,
+    };
+
+    if cli.health_check {
+        return Ok(health_check(&client, &relay_urls).await);
+    }
+
+    // A dry run always reports as TAP; there are no results to format.
+    let tap = cli.format == OutputFormat::Tap || cli.dry_run;
+
+    let mut out = Output::new(cli.output.as_deref())?;
+
+    // Normally there's one set of relays that multi-relay tests span. With
+    // --relay-matrix, the suite runs once per relay instead, and with
+    // --transport both, once per transport on top of that.
+    let relay_sets: Vec<(Vec<url::Url>, Option<String>)> = match cli.relay_matrix {
+        true => relay_urls
+            .iter()
+            .zip(&cli.relay)
+            .map(|(url, relay)| (vec![url.clone()], Some(relay.clone())))
+            .collect(),
+        false => vec![(relay_urls, None)],
+    };
+    let transports = match cli.transport {
+        Transport::Both => vec![Transport::Webtransport, Transport::RawQuic],
+        transport => vec![transport],
+    };
+    let mut targets = Vec::with_capacity(relay_sets.len() * transports.len());
+    for (urls, relay) in &relay_sets {
+        for &transport in &transports {
+            targets.push(Target {
+                relay_urls: urls
+                    .iter()
+                    .map(|url| transport.rewrite(url))
+                    .collect::<anyhow::Result<_>>()?,
+                relay: relay.clone(),
+                transport: (cli.transport == Transport::Both).then_some(transport),
+            });
+        }
+    }
+
+    let seed = cli.seed.unwrap_or_else(|| fastrand::u64(..));
+
+    // Every test against every target, in target order.
+    let jobs: Vec<(usize, &'static str)> = (0..targets.len())
+        .flat_map(|target| tests.iter().map(move |&name| (target, name)))
+        .collect();
+    let label = |target: usize, report: TestReport| TestReport {
+        relay: targets[target].relay.clone(),
+        transport: targets[target].transport.map(Transport::label),
+        ..report
+    };
+
+    // Up to here, an error means the invocation was bad. From here on the suite
+    // runs, and failing to write its results mustn't pass for "unsupported".
+    let result: anyhow::Result<i32> = async {
+        if tap {
+            writeln!(out, "TAP version 14")?;
+            writeln!(out, "# moq-dev-rs-client v0.1.0")?;
+            writeln!(out, "# Relay: {}", cli.relay.join(", "))?;
+            writeln!(out, "# Seed: {}", seed)?;
+            writeln!(out, "1..{}", jobs.len())?;
+        }
+
+        if cli.dry_run {
+            for (i, &(target, name)) in jobs.iter().enumerate() {
+                if i == 0 || jobs[i - 1].0 != target {
+                    if let Some(heading) = targets[target].heading() {
+                        writeln!(out, "{}", heading)?;
+                    }
+                }
+                let urls = &targets[target].relay_urls;
+                let reason = skip_reason(
+                    name,
+                    urls.len(),
+                    connect::is_ipv6(&urls[0]),
+                    cli.tls_verify_test,
+                    cli.auth_token.is_some() && urls[0].scheme() != "https",
+                )
+                .unwrap_or("dry run");
+                let transport = targets[target].transport.map(Transport::label);
+                let name = qualified_name(name, transport);
+                writeln!(out, "ok {} - {} # SKIP {}", i + 1, name, reason)?;
+            }
+            return Ok(EXIT_PASSED);
+        }
+
+        let mut runners = Vec::with_capacity(targets.len());
+        for target in &targets {
+            // Connect the shared session up front. If that fails, every test falls back
+            // to its own session and reports the failure itself.
+            let shared = match cli.reuse_connection {
+                true => connect_shared(&client, &target.relay_urls[0], &timeouts).await,
+                false => None,
+            };
+
+            runners.push(Runner {
+                client: client.clone(),
+                verifying_client: verifying_client.clone(),
+                shared: shared.map(|shared| std::sync::Arc::new(tokio::sync::Mutex::new(shared))),
+                relay_urls: target.relay_urls.clone(),
+                namespace: cli.namespace.clone(),
+                track: cli.track.clone(),
+                track_count: cli.track_count,
+                payload_bytes: cli.payload_bytes,
+                object_bytes: cli.object_bytes,
+                payload: payload.clone(),
+                chunk_bytes: cli.chunk_bytes as usize,
+                subscribers: cli.subscribers,
+                max_subscriptions: cli.max_subscriptions,
+                churn_cycles: cli.churn_cycles,
+                announce_count: cli.announce_count,
+                seed,
+                timeouts: timeouts.clone(),
+                repeat: cli.repeat.get(),
+                warmup: cli.warmup,
+                verbose_errors: cli.verbose_errors,
+                retry_timeouts: cli.retry_timeouts,
+                before: cli.before.clone(),
+                after: cli.after.clone(),
+            });
+        }
+
+        // Tests may finish out of order when run in parallel, so results are slotted
+        // by index and TAP lines are printed as soon as the next one in order is ready.
+        let mut slots: Vec<Option<TestReport>> = jobs.iter().map(|_| None).collect();
+        let started_at = std::time::SystemTime::now();
+        let mut printed = 0;
+        let mut queue = jobs.iter().copied().enumerate();
+        let mut running = tokio::task::JoinSet::new();
+
+        let budget = cli
+            .max_duration
+            .map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs));
+        let over_budget = async {
+            match budget {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(over_budget);
+
+        // On Ctrl-C, stop like the budget ran out but skip what's left rather than
+        // failing it, so the plan printed up front still adds up.
+        let interrupt = tokio::signal::ctrl_c();
+        tokio::pin!(interrupt);
+        let mut interrupted = false;
+        // The first failure, with --fail-fast.
+        let mut bailed = None;
+
+        // With several targets, head each one's block of results with what it ran against.
+        let print_tap = |out: &mut Output, i: usize, report: &TestReport| -> std::io::Result<()> {
+            let target = jobs[i].0;
+            if i == 0 || jobs[i - 1].0 != target {
+                if let Some(heading) = targets[target].heading() {
+                    writeln!(out, "{}", heading)?;
+                }
+            }
+            print_tap_result(out, i + 1, report, !cli.no_diagnostics)
+        };
+
+        loop {
+            while running.len() < cli.parallel.get() {
+                let Some((i, (target, name))) = queue.next() else {
+                    break;
+                };
+                let runner = runners[target].clone();
+                // Tag every log event with the test (and relay) it came from.
+                let span = match targets.len() > 1 {
+                    true => tracing::info_span!("test", test = name, relay = %runner.relay_urls[0]),
+                    false => tracing::info_span!("test", test = name),
+                };
+                running.spawn(async move { (i, run_one(name, &runner).instrument(span).await) });
+            }
+
+            let joined = tokio::select! {
+                joined = running.join_next() => joined,
+                _ = &mut over_budget => {
+                    // Dropping the running tests closes their sessions.
+                    running.shutdown().await;
+                    break;
+                }
+                Ok(()) = &mut interrupt => {
+                    running.shutdown().await;
+                    interrupted = true;
+                    break;
+                }
+            };
+            let Some(joined) = joined else {
+                break;
+            };
+            let (i, report) = joined.context("test task panicked")?;
+            let report = label(jobs[i].0, report);
+            if cli.format == OutputFormat::Jsonl {
+                print_json_line(&mut out, &report)?;
+            }
+            slots[i] = Some(report);
+
+            while let Some(Some(report)) = slots.get(printed) {
+                if tap && !cli.summary_only {
+                    print_tap(&mut out, printed, report)?;
+                }
+                printed += 1;
+            }
+
+            if cli.fail_fast
+                && slots[i]
+                    .as_ref()
+                    .is_some_and(|r| r.status == Status::Failed)
+            {
+                running.shutdown().await;
+                bailed = Some(i);
+                break;
+            }
+        }
+
+        let finished = slots.iter().flatten().count();
+
+        // Anything without a result by now was interrupted, bailed out on, or ran out of budget.
+        for (slot, &(target, name)) in slots.iter_mut().zip(&jobs) {
+            if slot.is_none() {
+                let report = match (interrupted, bailed) {
+                    (true, _) => TestReport::interrupted(name),
+                    (false, Some(_)) => TestReport::bailed_out(name),
+                    (false, None) => TestReport::over_budget(name),
+                };
+                let report = label(target, report);
+                if cli.format == OutputFormat::Jsonl {
+                    print_json_line(&mut out, &report)?;
+                }
+                *slot = Some(report);
+            }
+        }
+        let reports: Vec<TestReport> = slots.into_iter().flatten().collect();
+
+        let summary = Summary::new(&reports, seed);
+
+        if tap {
+            // Nothing after the failing test is printed; a bail out ends the stream.
+            let last = bailed.unwrap_or(reports.len() - 1);
+            if !cli.summary_only {
+                for (i, report) in reports.iter().enumerate().take(last + 1).skip(printed) {
+                    print_tap(&mut out, i, report)?;
+                }
+            } else {
+                writeln!(out, "# {}", summary.line(&reports))?;
+            }
+            if let Some(i) = bailed {
+                writeln!(out, "Bail out! {} failed", reports[i].label())?;
+            }
+            if interrupted {
+                writeln!(
+                    out,
+                    "# Interrupted: {} of {} tests did not finish",
+                    jobs.len() - finished,
+                    jobs.len()
+                )?;
+            }
+        }
+
+        for runner in &runners {
+            if let Some(shared) = &runner.shared {
+                shared.lock().await.conn.close(moq_lite::Error::Cancel);
+            }
+        }
+
+        match cli.format {
+            OutputFormat::Json => print_json(&mut out, &reports, &summary)?,
+            OutputFormat::Jsonl => print_json_line(&mut out, &summary)?,
+            OutputFormat::Pretty => {
+                // Escape codes would end up in the --output file too.
+                let color = cli.output.is_none()
+                    && std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+                print_pretty(&mut out, &reports, &summary, color, cli.summary_only)?;
+            }
+            OutputFormat::Tap => {}
+        }
+
+        if let Some(path) = &cli.junit {
+            std::fs::write(path, junit_xml(&reports, &summary))
+                .with_context(|| format!("failed to write JUnit report: {}", path.display()))?;
+        }
+
+        if let Some(path) = &cli.html {
+            std::fs::write(path, html_report(&reports, &summary)?)
+                .with_context(|| format!("failed to write HTML report: {}", path.display()))?;
+        }
+
+        if let Some(path) = &cli.metrics {
+            // Write then rename, so the collector never scrapes a half-written file.
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            std::fs::write(&tmp, prometheus_metrics(&reports, &summary))
+                .and_then(|()| std::fs::rename(&tmp, path))
+                .with_context(|| format!("failed to write metrics: {}", path.display()))?;
+        }
+
+        if let Some(path) = &cli.db {
+            db::Db::open(path)?.record(started_at, seed, &cli.relay.join(", "), &reports)?;
+        }
+
+        match interrupted {
+            true => Ok(EXIT_INTERRUPTED),
+            false => Ok(exit_code(&reports)),
+        }
+    }
+    .await;
+    result.or_else(|err| {
+        eprintln!("Error: {:?}", err);
+        Ok(EXIT_FAILED)
+    })
+}
+
+/// The exit code for a finished run: any assertion failure wins, otherwise
+/// whichever of timeouts or connection errors caused more failures.
+fn exit_code(reports: &[TestReport]) -> i32 {
+    let mut timeouts = 0;
+    let mut connect = 0;
+    for report in reports.iter().filter(|r| r.status == Status::Failed) {
+        match report.failure_kind.map(FailureKind::exit_code) {
+            Some(EXIT_TIMEOUT) => timeouts += 1,
+            Some(EXIT_CONNECT) => connect += 1,
+            _ => return EXIT_FAILED,
+        }
+    }
+
+    match (timeouts, connect) {
+        (0, 0) => EXIT_PASSED,
+        (timeouts, connect) if timeouts > connect => EXIT_TIMEOUT,
+        _ => EXIT_CONNECT,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// The result of a single test, as emitted by the machine-readable formats.
+#[derive(Serialize)]
+struct TestReport {
+    name: &'static str,
+    /// The relay the test ran against, with `--relay-matrix`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relay: Option<String>,
+    /// The transport the test ran over, with `--transport both`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transport: Option<&'static str>,
+    status: Status,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_kind: Option<FailureKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runs: Option<RunStats>,
+    /// Re-runs after a timeout, with `--retry-timeouts`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_retries: Option<u32>,
+    /// What the last run's `--before` hooks printed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before_hook_output: Option<String>,
+    /// What the last run's `--after` hooks printed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after_hook_output: Option<String>,
+    /// Cancelled or never started because `--max-duration` ran out.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    over_budget: bool,
+    /// The first failure's causes, outermost first, with `--verbose-errors`.
+    #[serde(rename = "backtrace", skip_serializing_if = "Option::is_none")]
+    error_chain: Option<Vec<String>>,
+    #[serde(flatten)]
+    diagnostics: Diagnostics,
+}
+
+/// A test's name, followed by the transport it ran over when that varies.
+fn qualified_name(name: &str, transport: Option<&str>) -> String {
+    match transport {
+        Some(transport) => format!("{} ({})", name, transport),
+        None => name.to_string(),
+    }
+}
+
+impl TestReport {
+    fn qualified_name(&self) -> String {
+        qualified_name(self.name, self.transport)
+    }
+
+    /// The qualified name, plus the relay with `--relay-matrix`.
+    fn label(&self) -> String {
+        match &self.relay {
+            Some(relay) => format!("{} ({})", self.qualified_name(), relay),
+            None => self.qualified_name(),
+        }
+    }
+
+    /// A report for a test that didn't run to completion, with nothing to show
+    /// but why.
+    fn new(name: &'static str, status: Status, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            relay: None,
+            transport: None,
+            status,
+            duration_ms: 0,
+            message: Some(message.into()),
+            failure_kind: None,
+            runs: None,
+            timeout_retries: None,
+            before_hook_output: None,
+            after_hook_output: None,
+            over_budget: false,
+            error_chain: None,
+            diagnostics: Diagnostics::default(),
+        }
+    }
+
+    /// A test that hadn't finished when `--max-duration` ran out.
+    fn over_budget(name: &'static str) -> Self {
+        Self {
+            failure_kind: Some(FailureKind::Timeout),
+            over_budget: true,
+            ..Self::new(name, Status::Failed, "suite budget exceeded")
+        }
+    }
+
+    /// A test that hadn't finished (or started) when `--fail-fast` stopped the run.
+    fn bailed_out(name: &'static str) -> Self {
+        Self::new(name, Status::Skipped, "not run: another test failed")
+    }
+
+    /// A test that hadn't finished (or started) when the run was interrupted.
+    fn interrupted(name: &'static str) -> Self {
+        Self::new(name, Status::Skipped, "interrupted")
+    }
+}
+
+/// What a single run of a test produced, before it's folded into a `TestReport`.
+struct TestOutcome {
+    status: Status,
+    diagnostics: Diagnostics,
+    failure: Option<FailureInfo>,
+}
+
+/// Why a run failed.
+struct FailureInfo {
+    kind: FailureKind,
+    message: String,
+    /// Each level of the error chain on its own, outermost first.
+    chain: Vec<String>,
+}
+
+/// The TLS alert a server sends when it supports none of the offered ALPNs (RFC 7301).
+const NO_APPLICATION_PROTOCOL: u8 = 120;
+
+/// Broad category of a failure, so results can be aggregated across runs.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FailureKind {
+    Timeout,
+    ConnectionRefused,
+    TlsError,
+    /// The relay accepted none of the protocols offered, e.g. with `--alpn`.
+    AlpnMismatch,
+    ProtocolError,
+    /// The relay refused our credentials, or the lack of them.
+    Unauthorized,
+    /// The relay speaks none of the MoQ versions offered, e.g. with `--moq-version`.
+    VersionMismatch,
+    Other,
+}
+
+impl FailureKind {
+    /// Classify an error by the first recognizable cause in its chain.
+    fn classify(err: &anyhow::Error) -> Self {
+        if err.downcast_ref::<AuthRejected>().is_some() {
+            return Self::Unauthorized;
+        }
+        if err.downcast_ref::<VersionRejected>().is_some() {
+            return Self::VersionMismatch;
+        }
+
+        err.chain()
+            .find_map(Self::from_cause)
+            .unwrap_or(Self::Other)
+    }
+
+    fn from_cause(cause: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        use moq_native::web_transport_quinn::{self, quinn};
+
+        if cause.is::<tokio::time::error::Elapsed>() {
+            return Some(Self::Timeout);
+        }
+
+        if cause.is::<moq_native::rustls::Error>() {
+            return Some(Self::TlsError);
+        }
+
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            return match err.kind() {
+                std::io::ErrorKind::ConnectionRefused => Some(Self::ConnectionRefused),
+                std::io::ErrorKind::TimedOut => Some(Self::Timeout),
+                _ => None,
+            };
+        }
+
+        // TLS alerts are carried as QUIC transport errors in the crypto range.
+        let is_tls = |code: quinn::TransportErrorCode| (0x100..0x200).contains(&u64::from(code));
+        let no_alpn = quinn::TransportErrorCode::crypto(NO_APPLICATION_PROTOCOL);
+
+        if let Some(err) = cause.downcast_ref::<quinn::ConnectionError>() {
+            return Some(match err {
+                quinn::ConnectionError::TimedOut => Self::Timeout,
+                quinn::ConnectionError::TransportError(err) if err.code == no_alpn => {
+                    Self::AlpnMismatch
+                }
+                quinn::ConnectionError::ConnectionClosed(close) if close.error_code == no_alpn => {
+                    Self::AlpnMismatch
+                }
+                quinn::ConnectionError::TransportError(err) if is_tls(err.code) => Self::TlsError,
+                quinn::ConnectionError::ConnectionClosed(close) if is_tls(close.error_code) => {
+                    Self::TlsError
+                }
+                quinn::ConnectionError::ConnectionClosed(close)
+                    if close.error_code == quinn::TransportErrorCode::CONNECTION_REFUSED =>
+                {
+                    Self::ConnectionRefused
+                }
+                quinn::ConnectionError::Reset => Self::ConnectionRefused,
+                quinn::ConnectionError::LocallyClosed | quinn::ConnectionError::CidsExhausted => {
+                    Self::Other
+                }
+                _ => Self::ProtocolError,
+            });
+        }
+
+        if let Some(err) = cause.downcast_ref::<web_transport_quinn::ClientError>() {
+            // The CONNECT error type is private, so a refusal's status is only in its message.
+            let refused = |err: &dyn std::fmt::Display| {
+                let message = err.to_string();
+                message
+                    .strip_prefix("http error status: ")
+                    .is_some_and(|status| status.starts_with("401") || status.starts_with("403"))
+            };
+
+            return match err {
+                web_transport_quinn::ClientError::HttpError(err) if refused(err) => {
+                    Some(Self::Unauthorized)
+                }
+                web_transport_quinn::ClientError::UnexpectedEnd
+                | web_transport_quinn::ClientError::SettingsError(_)
+                | web_transport_quinn::ClientError::HttpError(_) => Some(Self::ProtocolError),
+                _ => None,
+            };
+        }
+
+        if let Some(err) = cause.downcast_ref::<moq_lite::Error>() {
+            return Some(match err {
+                moq_lite::Error::Timeout => Self::Timeout,
+                moq_lite::Error::Unauthorized => Self::Unauthorized,
+                moq_lite::Error::Version => Self::VersionMismatch,
+                _ => Self::ProtocolError,
+            });
+        }
+
+        None
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::ConnectionRefused => "connection_refused",
+            Self::TlsError => "tls_error",
+            Self::AlpnMismatch => "alpn_mismatch",
+            Self::ProtocolError => "protocol_error",
+            Self::Unauthorized => "unauthorized",
+            Self::VersionMismatch => "version_mismatch",
+            Self::Other => "other",
+        }
+    }
+
+    /// The exit code for failing this way alone: timeouts and connection or
+    /// setup errors have their own, anything else is an assertion failure.
+    fn exit_code(self) -> i32 {
+        match self {
+            Self::Timeout => EXIT_TIMEOUT,
+            Self::ConnectionRefused
+            | Self::TlsError
+            | Self::AlpnMismatch
+            | Self::Unauthorized
+            | Self::VersionMismatch => EXIT_CONNECT,
+            Self::ProtocolError | Self::Other => EXIT_FAILED,
+        }
+    }
+}
+
+/// Marks an error as the relay turning away our `--auth-token`, for tests that
+/// can tell; moq-relay just drops the CONNECT request rather than answering 401.
+#[derive(Debug)]
+struct AuthRejected(&'static str);
+
+impl std::fmt::Display for AuthRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// Whether the relay closed the connection the way relays turn away
+/// credentials: moq-relay closes raw QUIC with the HTTP status as the code, and
+/// others may only say so in the reason.
+fn is_auth_close(close: &connect::PeerClosed) -> bool {
+    let reason = close.reason.to_ascii_lowercase();
+    matches!(close.code, 401 | 403)
+        || reason.contains("unauthorized")
+        || reason.contains("forbidden")
+}
+
+/// Marks an error as the relay refusing the only version `--moq-version` let
+/// us offer; over raw QUIC that looks like any other ALPN mismatch.
+#[derive(Debug)]
+struct VersionRejected(moq_lite::Version);
+
+impl std::fmt::Display for VersionRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "relay does not support {}", self.0)
+    }
+}
+
+/// Aggregate outcome and timing across `--repeat` runs of one test.
+#[derive(Serialize)]
+struct RunStats {
+    passed: usize,
+    total: usize,
+    min_ms: u128,
+    max_ms: u128,
+    mean_ms: u128,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    total: usize,
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    seed: u64,
+}
+
+impl Summary {
+    fn new(reports: &[TestReport], seed: u64) -> Self {
+        let count = |status| reports.iter().filter(|r| r.status == status).count();
+        Self {
+            kind: "summary",
+            total: reports.len(),
+            passed: count(Status::Passed),
+            failed: count(Status::Failed),
+            skipped: count(Status::Skipped),
+            seed,
+        }
+    }
+
+    /// The counts on one line, followed by which tests failed.
+    fn line(&self, reports: &[TestReport]) -> String {
+        let mut line = format!(
+            "{} passed, {} failed, {} skipped",
+            self.passed, self.failed, self.skipped
+        );
+        let failed: Vec<String> = reports
+            .iter()
+            .filter(|r| r.status == Status::Failed)
+            .map(TestReport::label)
+            .collect();
+        if !failed.is_empty() {
+            line += &format!("; failed: {}", failed.join(", "));
+        }
+        line
+    }
+}
+
+/// Results output: stdout, plus a copy in the `--output` file if there is one.
+///
+/// The copy is flushed line by line, so a run that dies partway still leaves
+/// valid (if incomplete) TAP behind.
+struct Output {
+    file: Option<std::io::LineWriter<std::fs::File>>,
+}
+
+impl Output {
+    fn new(path: Option<&std::path::Path>) -> anyhow::Result<Self> {
+        let file = match path {
+            Some(path) => {
+                let file = std::fs::File::create(path)
+                    .with_context(|| format!("failed to create output file: {}", path.display()))?;
+                Some(std::io::LineWriter::new(file))
+            }
+            None => None,
+        };
+        Ok(Self { file })
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::stdout().write_all(buf)?;
+        if let Some(file) = &mut self.file {
+            file.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()?;
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A results table for `--format pretty`, one row per test, then the totals.
+fn print_pretty(
+    out: &mut impl Write,
+    reports: &[TestReport],
+    summary: &Summary,
+    color: bool,
+    summary_only: bool,
+) -> std::io::Result<()> {
+    if summary_only {
+        return writeln!(out, "{}", summary.line(reports));
+    }
+
+    let paint = |code: &str, text: &str| match color {
+        true => format!("\x1b[{}m{}\x1b[0m", code, text),
+        false => text.to_string(),
+    };
+
+    let width = reports.iter().map(|r| r.label().len()).max().unwrap_or(0);
+
+    for report in reports {
+        let status = match report.status {
+            Status::Passed => paint("32", "PASS"),
+            Status::Failed => paint("31", "FAIL"),
+            Status::Skipped => paint("33", "SKIP"),
+        };
+        writeln!(
+            out,
+            "{}  {:<width$}  {:>6}ms",
+            status,
+            report.label(),
+            report.duration_ms,
+            width = width
+        )?;
+        if let Some(message) = &report.message {
+            writeln!(out, "      {}", paint("2", message))?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} passed, {} failed, {} skipped",
+        summary.passed, summary.failed, summary.skipped
+    )?;
+    writeln!(out, "Seed: {}", summary.seed)
+}
+
+fn print_json(
+    out: &mut impl Write,
+    reports: &[TestReport],
+    summary: &Summary,
+) -> anyhow::Result<()> {
+    let mut values = Vec::with_capacity(reports.len() + 1);
+    for report in reports {
+        values.push(serde_json::to_value(report)?);
+    }
+    values.push(serde_json::to_value(summary)?);
+    writeln!(out, "{}", serde_json::to_string_pretty(&values)?)?;
+    Ok(())
+}
+
+/// Print one compact JSON object and flush, so `--format jsonl` can be tailed.
+fn print_json_line(out: &mut impl Write, value: &impl Serialize) -> anyhow::Result<()> {
+    writeln!(out, "{}", serde_json::to_string(value)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// One entry in `--list --format json` (or `jsonl`).
+#[derive(Serialize)]
+struct TestListing {
+    name: &'static str,
+    tags: &'static [&'static str],
+    skipped: bool,
+    skip_reason: Option<&'static str>,
+    timeout_ms: u128,
+}
+
+/// One entry in `--list-skipped --format json` (or `jsonl`).
+#[derive(Serialize)]
+struct SkippedListing {
+    name: &'static str,
+    reason: &'static str,
+}
+
+/// Connect the session `--reuse-connection` shares between tests, or explain
+/// on stderr why not.
+async fn connect_shared(
+    client: &connect::Client,
+    url: &url::Url,
+    timeouts: &Timeouts,
+) -> Option<SharedSession> {
+    let origin = Origin::produce();
+    let client = client.clone().with_publish(origin.consume());
+    let timeout = timeouts.get("setup-only");
+    let conn = match tokio::time::timeout(timeout, client.connect(url.clone())).await {
+        Ok(conn) => conn,
+        Err(_) => Err(anyhow::anyhow!("timeout after {}ms", timeout.as_millis())),
+    };
+    match conn {
+        Ok(conn) => Some(SharedSession { conn, origin }),
+        Err(err) => {
+            eprintln!(
+                "Failed to connect shared session to {}, not reusing it: {:#}",
+                url, err
+            );
+            None
+        }
+    }
+}
+
+/// How long `--health-check` gives each relay to complete the handshake.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handshake with each relay and close again, returning the exit code.
+async fn health_check(client: &connect::Client, urls: &[url::Url]) -> i32 {
+    for url in urls {
+        let conn =
+            match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, client.connect(url.clone())).await {
+                Ok(conn) => conn,
+                Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!(
+                    "timeout after {}ms",
+                    HEALTH_CHECK_TIMEOUT.as_millis()
+                ))),
+            };
+
+        match conn {
+            Ok(mut conn) => conn.close(moq_lite::Error::Cancel),
+            Err(err) => {
+                eprintln!("relay: unhealthy: {}: {:#}", url, err);
+                return FailureKind::classify(&err).exit_code();
+            }
+        }
+    }
+
+    println!("relay: healthy");
+    EXIT_PASSED
+}
+
+/// Describe the suite as it would run with the current relays and timeouts.
+fn print_listing(
+    tests: &[&'static str],
+    relays: usize,
+    ipv6: bool,
+    tls_verify: bool,
+    raw_auth: bool,
+    timeouts: &Timeouts,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let listing: Vec<TestListing> = tests
+        .iter()
+        .map(|&name| {
+            let skip_reason = skip_reason(name, relays, ipv6, tls_verify, raw_auth);
+            TestListing {
+                name,
+                tags: tags(name),
+                skipped: skip_reason.is_some(),
+                skip_reason,
+                timeout_ms: timeouts.get(name).as_millis(),
+            }
+        })
+        .collect();
+    match format {
+        OutputFormat::Jsonl => {
+            for entry in &listing {
+                println!("{}", serde_json::to_string(entry)?);
+            }
+        }
+        _ => println!("{}", serde_json::to_string_pretty(&listing)?),
+    }
+    Ok(())
+}
+
+/// Render a single JUnit `<testsuite>` with one `<testcase>` per test.
+fn junit_xml(reports: &[TestReport], summary: &Summary) -> String {
+    let seconds = |ms: u128| format!("{:.3}", ms as f64 / 1000.0);
+    let total_ms = reports.iter().map(|r| r.duration_ms).sum();
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"moq-dev-rs-client\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\">\n",
+        summary.total,
+        summary.failed,
+        summary.skipped,
+        seconds(total_ms)
+    ));
+
+    for report in reports {
+        xml.push_str(&format!(
+            "  <testcase classname=\"{}\" name=\"{}\" time=\"{}\"",
+            xml_escape(report.relay.as_deref().unwrap_or("moq-dev-rs-client")),
+            xml_escape(&report.qualified_name()),
+            seconds(report.duration_ms)
+        ));
+
+        let message = xml_escape(report.message.as_deref().unwrap_or_default());
+        match report.status {
+            Status::Passed => xml.push_str("/>\n"),
+            Status::Failed => {
+                let kind = report.failure_kind.unwrap_or(FailureKind::Other);
+                xml.push_str(">\n");
+                xml.push_str(&format!(
+                    "    <failure type=\"{}\" message=\"{}\">{}</failure>\n",
+                    kind.as_str(),
+                    message,
+                    message
+                ));
+                xml.push_str("  </testcase>\n");
+            }
+            Status::Skipped => {
+                xml.push_str(">\n");
+                xml.push_str(&format!("    <skipped message=\"{}\"/>\n", message));
+                xml.push_str("  </testcase>\n");
+            }
+        }
+    }
+
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+/// Render Prometheus text-format metrics: whether each test that ran passed and
+/// how long it took, plus gauges for the suite as a whole.
+fn prometheus_metrics(reports: &[TestReport], summary: &Summary) -> String {
+    let labels = |report: &TestReport| {
+        let mut labels = format!("test=\"{}\"", prometheus_escape(report.name));
+        if let Some(transport) = report.transport {
+            labels.push_str(&format!(",transport=\"{}\"", prometheus_escape(transport)));
+        }
+        if let Some(relay) = &report.relay {
+            labels.push_str(&format!(",relay=\"{}\"", prometheus_escape(relay)));
+        }
+        labels
+    };
+    // Skipped tests didn't run, so they'd only look like failures here.
+    let ran: Vec<&TestReport> = reports
+        .iter()
+        .filter(|r| r.status != Status::Skipped)
+        .collect();
+
+    let mut out = String::new();
+    out.push_str("# HELP moq_interop_test_passed Whether the test passed.\n");
+    out.push_str("# TYPE moq_interop_test_passed gauge\n");
+    for report in &ran {
+        out.push_str(&format!(
+            "moq_interop_test_passed{{{}}} {}\n",
+            labels(report),
+            u8::from(report.status == Status::Passed)
+        ));
+    }
+
+    out.push_str("# HELP moq_interop_test_duration_seconds How long the test took.\n");
+    out.push_str("# TYPE moq_interop_test_duration_seconds gauge\n");
+    for report in &ran {
+        out.push_str(&format!(
+            "moq_interop_test_duration_seconds{{{}}} {:.3}\n",
+            labels(report),
+            report.duration_ms as f64 / 1000.0
+        ));
+    }
+
+    out.push_str("# HELP moq_interop_suite_tests Tests in the last run, by status.\n");
+    out.push_str("# TYPE moq_interop_suite_tests gauge\n");
+    for (status, count) in [
+        ("passed", summary.passed),
+        ("failed", summary.failed),
+        ("skipped", summary.skipped),
+    ] {
+        out.push_str(&format!(
+            "moq_interop_suite_tests{{status=\"{}\"}} {}\n",
+            status, count
+        ));
+    }
+
+    out.push_str("# HELP moq_interop_suite_success Whether every test in the last run passed or was skipped.\n");
+    out.push_str("# TYPE moq_interop_suite_success gauge\n");
+    out.push_str(&format!(
+        "moq_interop_suite_success {}\n",
+        u8::from(summary.failed == 0)
+    ));
+
+    // Lets alerts tell a passing suite from one that stopped running.
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    out.push_str(
+        "# HELP moq_interop_suite_last_run_timestamp_seconds When the last run finished.\n",
+    );
+    out.push_str("# TYPE moq_interop_suite_last_run_timestamp_seconds gauge\n");
+    out.push_str(&format!(
+        "moq_interop_suite_last_run_timestamp_seconds {}\n",
+        now.as_secs()
+    ));
+    out
+}
+
+/// Escape a Prometheus label value: backslashes, double quotes and newlines.
+fn prometheus_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escape text for use in XML content or attribute values.
+///
+/// Control characters other than whitespace aren't allowed in XML 1.0 at all,
+/// so they're replaced rather than escaped.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' => out.push_str("&#10;"),
+            '\t' | '\r' => out.push(c),
+            c if c.is_control() => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Styles for `--html`, inlined so the report is a single file.
+const HTML_STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; vertical-align: top; }
+.passed { color: #1a7f37; }
+.failed { color: #cf222e; }
+.skipped { color: #9a6700; }
+.bar { background: #8cb4e8; height: 0.8em; min-width: 1px; }
+details summary { cursor: pointer; }
+pre { background: #f6f8fa; padding: 0.6em; overflow-x: auto; }
+";
+
+/// The run as a standalone HTML page, for `--html`: a row per test with its
+/// duration drawn to scale, and its message and diagnostics folded away.
+fn html_report(reports: &[TestReport], summary: &Summary) -> std::io::Result<String> {
+    let longest = reports
+        .iter()
+        .map(|r| r.duration_ms)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>moq-dev-rs-client results</title>\n");
+    html.push_str(&format!("<style>{}</style>\n</head>\n<body>\n", HTML_STYLE));
+    html.push_str("<h1>moq-dev-rs-client results</h1>\n");
+    html.push_str(&format!("<p>{}</p>\n", xml_escape(&summary.line(reports))));
+    html.push_str(
+        "<table>\n<tr><th>Test</th><th>Status</th><th>Duration</th><th>Details</th></tr>\n",
+    );
+
+    for report in reports {
+        let (status, diagnostics) = match report.status {
+            Status::Passed => {
+                let mut out = Vec::new();
+                print_diagnostics(&mut out, report)?;
+                ("passed", out)
+            }
+            Status::Failed => {
+                let mut out = Vec::new();
+                print_failure_diagnostics(&mut out, report)?;
+                ("failed", out)
+            }
+            Status::Skipped => ("skipped", Vec::new()),
+        };
+
+        // Skipped tests have only their reason to show.
+        let summary = xml_escape(report.message.as_deref().unwrap_or("diagnostics"));
+        let details = match diagnostics.is_empty() {
+            true => summary,
+            false => format!(
+                "<details><summary>{}</summary><pre>{}</pre></details>",
+                summary,
+                xml_escape(&String::from_utf8_lossy(&diagnostics))
+            ),
+        };
+
+        html.push_str(&format!(
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td><div class=\"bar\" style=\"width: {:.1}%\"></div>{} ms</td><td>{}</td></tr>\n",
+            xml_escape(&report.label()),
+            status,
+            status,
+            report.duration_ms as f64 * 100.0 / longest as f64,
+            report.duration_ms,
+            details
+        ));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+#[derive(Default, Serialize)]
+struct Diagnostics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher_connection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_connection_id: Option<String>,
+    /// The session `reconnect` closed before connecting again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    initial_connection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher_protocol_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_protocol_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher_rtt_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_rtt_ms: Option<f64>,
+    /// Time from starting to connect until the session was ready.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connect_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher_connect_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_connect_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connect_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher_connect_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_connect_attempts: Option<u32>,
+    /// UDP bytes over the test's connections, summed if there are two.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_sent: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_received: Option<u64>,
+    /// The `--congestion` controller the test's connections used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    congestion: Option<&'static str>,
+    /// The `--simulate-loss` and `--simulate-jitter-ms` the test ran under.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    impairment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_transferred: Option<u64>,
+    /// Per-object send-to-receive latency, from `data-roundtrip`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_p50_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_p99_ms: Option<f64>,
+    /// The `--seed` a test generated its data from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    /// How `zero-rtt-resume` fared: `accepted`, `rejected`, or `unavailable`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    early_data: Option<&'static str>,
+    /// How much faster the resumed connection was set up than the first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handshake_saved_ms: Option<f64>,
+    /// From subscribing to the first object, in `time-to-first-object`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttfo_ms: Option<f64>,
+    /// Whether `goaway-handling`'s session was closed by our side: a GOAWAY,
+    /// or a protocol error moq-lite gave up on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_close: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track_count: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority_order: Option<String>,
+    /// How much sooner `priority-delivery`'s high-priority groups arrived on
+    /// average; negative if they were slower.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_delta_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shared_connection: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscribe_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throughput_mbps: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    close_codes: Option<String>,
+    /// Whether the relay closed the session in `relay-initiated-close`: `closed` or `none`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relay_close: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    close_code: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    close_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reannounce: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum_match: Option<bool>,
+    /// Every subscriber's connection ID, in order, for tests with more than one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriber_connection_ids: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscribers_complete: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_survived: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announced_path: Option<String>,
+    /// Whether the relay passed on `binary-names`' namespace and track name byte for byte.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    names_preserved: Option<bool>,
+    /// Whether `authenticated-announce` sent an `--auth-token`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authenticated: Option<bool>,
+    /// Objects received on each track, as `name=count` pairs (`interleaved-tracks`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    track_objects: Option<String>,
+    /// Objects a test published, and how many of them arrived.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    objects_expected: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    objects_delivered: Option<u64>,
+    /// Places `live-stream` skipped one or more groups, and the longest wait
+    /// between two groups arriving.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gaps_detected: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_interarrival_ms: Option<f64>,
+    /// The most `backpressure`'s subscriber had received but not yet read, and
+    /// the most its publisher had written but not yet sent, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_buffered_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher_backlog_bytes: Option<u64>,
+    /// What the relay's SERVER_SETUP held in `setup-params`: the version it
+    /// selected, the parameter types it sent in order, and the values of the
+    /// ones we check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    setup_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    setup_parameters: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_request_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    implementation: Option<String>,
+    /// Announcements plus withdrawals of its namespace that `announce-churn`'s subscriber saw.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announce_transitions: Option<u32>,
+    /// How long `bulk-announce`'s subscriber took to discover every broadcast.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    discovery_ms: Option<f64>,
+    /// How many of its broadcasts `backlog-announce`'s late subscriber discovered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announced_count: Option<usize>,
+    /// Group sequence numbers in the order the subscriber received them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_order: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    out_of_order: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    early_subscription: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_subscribe: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    malformed_namespace: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    malformed_namespace_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    second_publisher_connection_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    second_subscriber_connection_id: Option<String>,
+    /// Whether `subscribe-abort`'s second subscriber received data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    second_subscriber_received: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace_conflict: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resubscribe: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriptions_succeeded: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscription_limit: Option<&'static str>,
+}
+
+impl Diagnostics {
+    /// Connection diagnostics for a test with a single connection.
+    fn single(conn: &connect::Connection) -> Self {
+        Self {
+            connection_id: Some(conn.id()),
+            protocol_version: Some(conn.version().to_string()),
+            rtt_ms: Some(millis(conn.rtt())),
+            connect_ms: Some(millis(conn.connect_time())),
+            connect_attempts: conn.attempts(),
+            bytes_sent: Some(conn.bytes_sent()),
+            bytes_received: Some(conn.bytes_received()),
+            congestion: conn.congestion().map(connect::Congestion::label),
+            impairment: conn.impairment().map(|impairment| impairment.label()),
+            ..Default::default()
+        }
+    }
+
+    /// Connection diagnostics for a test with a publisher and a subscriber.
+    fn pub_sub(publisher: &connect::Connection, subscriber: &connect::Connection) -> Self {
+        Self {
+            publisher_connection_id: Some(publisher.id()),
+            subscriber_connection_id: Some(subscriber.id()),
+            publisher_protocol_version: Some(publisher.version().to_string()),
+            subscriber_protocol_version: Some(subscriber.version().to_string()),
+            publisher_rtt_ms: Some(millis(publisher.rtt())),
+            subscriber_rtt_ms: Some(millis(subscriber.rtt())),
+            publisher_connect_ms: Some(millis(publisher.connect_time())),
+            subscriber_connect_ms: Some(millis(subscriber.connect_time())),
+            publisher_connect_attempts: publisher.attempts(),
+            subscriber_connect_attempts: subscriber.attempts(),
+            bytes_sent: Some(publisher.bytes_sent() + subscriber.bytes_sent()),
+            bytes_received: Some(publisher.bytes_received() + subscriber.bytes_received()),
+            congestion: publisher.congestion().map(connect::Congestion::label),
+            impairment: publisher.impairment().map(|impairment| impairment.label()),
+            ..Default::default()
+        }
+    }
+}
+
+/// The nearest-rank `pct`th percentile of `sorted`, which must not be empty.
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let rank = (sorted.len() * pct).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+/// A duration in milliseconds, to microsecond precision.
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+/// Run a single test `repeat` times (or record its skip) and time it.
+///
+/// The test passes only if every run passes. Diagnostics come from the last
+/// passing run and the message from the first failing one. A run that times
+/// out is retried, up to `--retry-timeouts` times, and only its last attempt counts.
+async fn run_one(name: &'static str, runner: &Runner) -> TestReport {
+    let repeat = runner.repeat;
+
+    let ipv6 = connect::is_ipv6(&runner.relay_urls[0]);
+    let tls_verify = runner.verifying_client.is_some();
+    let raw_auth = runner.client.auth_token().is_some() && runner.relay_urls[0].scheme() != "https";
+    if let Some(reason) = skip_reason(name, runner.relay_urls.len(), ipv6, tls_verify, raw_auth) {
+        return TestReport::new(name, Status::Skipped, reason);
+    }
+
+    let mut durations = Vec::with_capacity(repeat);
+    let mut passed = 0;
+    let mut diagnostics = Diagnostics::default();
+    let mut message = None;
+    let mut failure_kind = None;
+    let mut timeout_retries = 0;
+    let mut before_hook_output = None;
+    let mut after_hook_output = None;
+    let mut error_chain = None;
+    let relay = &runner.relay_urls[0];
+
+    // A warmup run counts for nothing, whatever its outcome, and skips the hooks.
+    if runner.warmup {
+        let shared = match &runner.shared {
+            Some(shared) if REUSABLE_TESTS.contains(&name) => Some(shared.lock().await),
+            _ => None,
+        };
+        let shared = shared.as_deref().filter(|shared| !shared.conn.is_closed());
+        let outcome = run_test(name, runner, shared).await;
+        tracing::info!(ok = outcome.status == Status::Passed, "warmup run finished");
+    }
+
+    for run in 1..=repeat {
+        let mut elapsed = 0;
+        let mut retries = 0;
+        let outcome = loop {
+            // Wait our turn for the shared session before the clock starts; a test
+            // that closed it (or a relay that dropped it) means going back to a fresh one.
+            let shared = match &runner.shared {
+                Some(shared) if REUSABLE_TESTS.contains(&name) => Some(shared.lock().await),
+                _ => None,
+            };
+            let shared = shared.as_deref().filter(|shared| !shared.conn.is_closed());
+
+            let before = run_hooks(&runner.before, name, relay).await;
+            let start = Instant::now();
+            let outcome = match before.as_ref().map(|(_, result)| result) {
+                Some(Err(err)) => TestOutcome {
+                    status: Status::Failed,
+                    diagnostics: Diagnostics::default(),
+                    failure: Some(FailureInfo {
+                        kind: FailureKind::Other,
+                        message: format!("{:#}", err),
+                        chain: err.chain().map(ToString::to_string).collect(),
+                    }),
+                },
+                _ => run_test(name, runner, shared).await,
+            };
+            elapsed += start.elapsed().as_millis();
+
+            let after = run_hooks(&runner.after, name, relay).await;
+            if let Some((_, Err(err))) = &after {
+                tracing::warn!("{:#}", err);
+            }
+            before_hook_output = before.map(|(output, _)| output);
+            after_hook_output = after.map(|(output, _)| output);
+
+            tracing::info!(
+                run,
+                ok = outcome.status == Status::Passed,
+                "test run finished"
+            );
+
+            match &outcome.failure {
+                Some(failure)
+                    if failure.kind == FailureKind::Timeout && retries < runner.retry_timeouts =>
+                {
+                    retries += 1;
+                    tracing::warn!(run, retry = retries, error = %failure.message, "retrying after timeout");
+                }
+                _ => break outcome,
+            }
+        };
+        durations.push(elapsed);
+        timeout_retries += retries;
+
+        match outcome.failure {
+            None => {
+                passed += 1;
+                diagnostics = outcome.diagnostics;
+            }
+            Some(failure) if message.is_none() => {
+                message = Some(match repeat {
+                    1 => failure.message,
+                    _ => format!("run {}/{}: {}", run, repeat, failure.message),
+                });
+                failure_kind = Some(failure.kind);
+                error_chain = runner.verbose_errors.then_some(failure.chain);
+            }
+            Some(_) => {}
+        }
+    }
+
+    let runs = (repeat > 1).then(|| RunStats {
+        passed,
+        total: repeat,
+        min_ms: durations.iter().copied().min().unwrap_or_default(),
+        max_ms: durations.iter().copied().max().unwrap_or_default(),
+        mean_ms: durations.iter().sum::<u128>() / repeat as u128,
+    });
+
+    TestReport {
+        name,
+        relay: None,
+        transport: None,
+        status: match message {
+            None => Status::Passed,
+            Some(_) => Status::Failed,
+        },
+        duration_ms: durations.iter().sum(),
+        message,
+        failure_kind,
+        runs,
+        timeout_retries: (timeout_retries > 0).then_some(timeout_retries),
+        before_hook_output,
+        after_hook_output,
+        over_budget: false,
+        error_chain,
+        diagnostics,
+    }
+}
+
+/// Run the `--before` or `--after` commands for `name` in order, stopping at the
+/// first that fails. Returns what they printed, stdout then stderr, and whether
+/// they all succeeded; `None` if the test has no hooks.
+async fn run_hooks(
+    hooks: &[(String, String)],
+    name: &str,
+    relay: &url::Url,
+) -> Option<(String, anyhow::Result<()>)> {
+    if !hooks.iter().any(|(test, _)| test == name) {
+        return None;
+    }
+
+    let mut output = String::new();
+    for (test, command) in hooks {
+        if test != name {
+            continue;
+        }
+        tracing::debug!(%command, "running hook");
+        // Killed if it outlives HOOK_TIMEOUT, or the test is dropped by
+        // --max-duration, --fail-fast or an interrupt.
+        let child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("TESTCASE", name)
+            .env("RELAY_URL", relay.as_str())
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let result = match tokio::time::timeout(HOOK_TIMEOUT, child).await {
+            Err(_) => Err(anyhow::anyhow!(
+                "hook `{}` timed out after {:?}",
+                command,
+                HOOK_TIMEOUT
+            )),
+            Ok(Ok(out)) => {
+                output.push_str(&String::from_utf8_lossy(&out.stdout));
+                output.push_str(&String::from_utf8_lossy(&out.stderr));
+                match out.status.success() {
+                    true => Ok(()),
+                    false => Err(anyhow::anyhow!("hook `{}` failed: {}", command, out.status)),
+                }
+            }
+            Ok(Err(err)) => {
+                Err(anyhow::Error::new(err).context(format!("failed to run hook `{}`", command)))
+            }
+        };
+        if result.is_err() {
+            return Some((trim_hook_output(output), result));
+        }
+    }
+    Some((trim_hook_output(output), Ok(())))
+}
+
+/// Keep only the end of long hook output, which is where errors tend to be.
+fn trim_hook_output(output: String) -> String {
+    let output = output.trim_end();
+    match output.len().checked_sub(HOOK_OUTPUT_LIMIT) {
+        Some(start) if start > 0 => {
+            let start = (start..output.len())
+                .find(|&i| output.is_char_boundary(i))
+                .unwrap_or(output.len());
+            format!("...{}", &output[start..])
+        }
+        _ => output.to_string(),
+    }
+}
+
+/// Why a test can't run against the given relays, if it can't. `ipv6` says
+/// whether the first relay is addressed by IPv6, `tls_verify` whether
+/// `--tls-verify-test` was given, and `raw_auth` whether an `--auth-token`
+/// would have to go to a raw QUIC relay.
+fn skip_reason(
+    name: &str,
+    relays: usize,
+    ipv6: bool,
+    tls_verify: bool,
+    raw_auth: bool,
+) -> Option<&'static str> {
+    let find = |tests: &[(&str, &'static str)]| {
+        tests
+            .iter()
+            .find(|(skipped, _)| *skipped == name)
+            .map(|(_, reason)| *reason)
+    };
+
+    find(SKIPPED_TESTS)
+        .or_else(|| match relays {
+            1 => find(MULTI_RELAY_TESTS),
+            _ => None,
+        })
+        .or_else(|| match ipv6 {
+            true => None,
+            false => find(IPV6_TESTS),
+        })
+        .or_else(|| match tls_verify {
+            true => None,
+            false => find(TLS_VERIFY_TESTS),
+        })
+        .or_else(|| match raw_auth {
+            true => find(AUTH_TOKEN_TESTS),
+            false => None,
+        })
+}
+
+fn print_tap_result(
+    out: &mut impl Write,
+    num: usize,
+    report: &TestReport,
+    diagnostics: bool,
+) -> std::io::Result<()> {
+    match report.status {
+        Status::Passed => {
+            writeln!(out, "ok {} - {}", num, report.qualified_name())?;
+            if diagnostics {
+                print_diagnostics(out, report)?;
+            }
+        }
+        Status::Failed if report.over_budget => {
+            let reason = report.message.as_deref().unwrap_or_default();
+            let name = report.qualified_name();
+            writeln!(out, "not ok {} - {} # TIMEOUT {}", num, name, reason)?;
+        }
+        Status::Failed if diagnostics => {
+            writeln!(out, "not ok {} - {}", num, report.qualified_name())?;
+            print_failure_diagnostics(out, report)?;
+        }
+        Status::Failed => {
+            // The comment has to stay on one line.
+            let message = report.message.as_deref().unwrap_or_default();
+            let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+            let name = report.qualified_name();
+            writeln!(out, "not ok {} - {} # {}", num, name, message)?;
+        }
+        Status::Skipped => {
+            let reason = report.message.as_deref().unwrap_or_default();
+            writeln!(
+                out,
+                "ok {} - {} # SKIP {}",
+                num,
+                report.qualified_name(),
+                reason
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn print_run_stats(out: &mut impl Write, report: &TestReport) -> std::io::Result<()> {
+    if let Some(runs) = &report.runs {
+        writeln!(out, "  runs_passed: {}/{}", runs.passed, runs.total)?;
+        writeln!(out, "  duration_min_ms: {}", runs.min_ms)?;
+        writeln!(out, "  duration_max_ms: {}", runs.max_ms)?;
+        writeln!(out, "  duration_mean_ms: {}", runs.mean_ms)?;
+    }
+    if let Some(retries) = report.timeout_retries {
+        writeln!(out, "  timeout_retries: {}", retries)?;
+    }
+    if let Some(output) = &report.before_hook_output {
+        print_block(out, "before_hook_output", output)?;
+    }
+    if let Some(output) = &report.after_hook_output {
+        print_block(out, "after_hook_output", output)?;
+    }
+    Ok(())
+}
+
+/// Print multi-line text as a YAML literal block.
+fn print_block(out: &mut impl Write, key: &str, text: &str) -> std::io::Result<()> {
+    if text.is_empty() {
+        return writeln!(out, "  {}: \"\"", key);
+    }
+    writeln!(out, "  {}: |-", key)?;
+    for line in text.lines() {
+        writeln!(out, "    {}", line)?;
+    }
+    Ok(())
+}
+
+fn print_diagnostics(out: &mut impl Write, report: &TestReport) -> std::io::Result<()> {
+    let diag = &report.diagnostics;
+    writeln!(out, "  ---")?;
+    writeln!(out, "  duration_ms: {}", report.duration_ms)?;
+    print_run_stats(out, report)?;
+    if let Some(id) = &diag.connection_id {
+        writeln!(out, "  connection_id: {}", id)?;
+    }
+    if let Some(id) = &diag.publisher_connection_id {
+        writeln!(out, "  publisher_connection_id: {}", id)?;
+    }
+    if let Some(id) = &diag.subscriber_connection_id {
+        writeln!(out, "  subscriber_connection_id: {}", id)?;
+    }
+    if let Some(ids) = &diag.subscriber_connection_ids {
+        writeln!(out, "  subscriber_connection_ids: {}", ids)?;
+    }
+    if let Some(id) = &diag.initial_connection_id {
+        writeln!(out, "  initial_connection_id: {}", id)?;
+    }
+    if let Some(version) = &diag.protocol_version {
+        writeln!(out, "  protocol_version: {}", version)?;
+    }
+    if let Some(version) = &diag.publisher_protocol_version {
+        writeln!(out, "  publisher_protocol_version: {}", version)?;
+    }
+    if let Some(version) = &diag.subscriber_protocol_version {
+        writeln!(out, "  subscriber_protocol_version: {}", version)?;
+    }
+    if let Some(rtt) = diag.rtt_ms {
+        writeln!(out, "  rtt_ms: {}", rtt)?;
+    }
+    if let Some(rtt) = diag.publisher_rtt_ms {
+        writeln!(out, "  publisher_rtt_ms: {}", rtt)?;
+    }
+    if let Some(rtt) = diag.subscriber_rtt_ms {
+        writeln!(out, "  subscriber_rtt_ms: {}", rtt)?;
+    }
+    if let Some(connect) = diag.connect_ms {
+        writeln!(out, "  connect_ms: {}", connect)?;
+    }
+    if let Some(connect) = diag.publisher_connect_ms {
+        writeln!(out, "  publisher_connect_ms: {}", connect)?;
+    }
+    if let Some(connect) = diag.subscriber_connect_ms {
+        writeln!(out, "  subscriber_connect_ms: {}", connect)?;
+    }
+    if let Some(attempts) = diag.connect_attempts {
+        writeln!(out, "  connect_attempts: {}", attempts)?;
+    }
+    if let Some(attempts) = diag.publisher_connect_attempts {
+        writeln!(out, "  publisher_connect_attempts: {}", attempts)?;
+    }
+    if let Some(attempts) = diag.subscriber_connect_attempts {
+        writeln!(out, "  subscriber_connect_attempts: {}", attempts)?;
+    }
+    if let Some(bytes) = diag.bytes_sent {
+        writeln!(out, "  bytes_sent: {}", bytes)?;
+    }
+    if let Some(bytes) = diag.bytes_received {
+        writeln!(out, "  bytes_received: {}", bytes)?;
+    }
+    if let Some(congestion) = diag.congestion {
+        writeln!(out, "  congestion: {}", congestion)?;
+    }
+    if let Some(impairment) = &diag.impairment {
+        writeln!(out, "  impairment: {}", impairment)?;
+    }
+    if let Some(bytes) = diag.bytes_transferred {
+        writeln!(out, "  bytes_transferred: {}", bytes)?;
+    }
+    if let Some(latency) = diag.latency_p50_ms {
+        writeln!(out, "  latency_p50_ms: {}", latency)?;
+    }
+    if let Some(latency) = diag.latency_p99_ms {
+        writeln!(out, "  latency_p99_ms: {}", latency)?;
+    }
+    if let Some(seed) = diag.seed {
+        writeln!(out, "  seed: {}", seed)?;
+    }
+    if let Some(early_data) = diag.early_data {
+        writeln!(out, "  early_data: {}", early_data)?;
+    }
+    if let Some(saved) = diag.handshake_saved_ms {
+        writeln!(out, "  handshake_saved_ms: {}", saved)?;
+    }
+    if let Some(ttfo) = diag.ttfo_ms {
+        writeln!(out, "  ttfo_ms: {}", ttfo)?;
+    }
+    if let Some(close) = diag.local_close {
+        writeln!(out, "  local_close: {}", close)?;
+    }
+    if let Some(count) = diag.track_count {
+        writeln!(out, "  track_count: {}", count)?;
+    }
+    if let Some(order) = &diag.priority_order {
+        writeln!(out, "  priority_order: {}", order)?;
+    }
+    if let Some(delta) = diag.latency_delta_ms {
+        writeln!(out, "  latency_delta_ms: {}", delta)?;
+    }
+    if let Some(shared) = diag.shared_connection {
+        writeln!(out, "  shared_connection: {}", shared)?;
+    }
+    if let Some(throughput) = diag.throughput_mbps {
+        writeln!(out, "  throughput_mbps: {}", throughput)?;
+    }
+    if let Some(codes) = &diag.close_codes {
+        writeln!(out, "  close_codes: {}", codes)?;
+    }
+    if let Some(close) = diag.relay_close {
+        writeln!(out, "  relay_close: {}", close)?;
+    }
+    if let Some(code) = diag.close_code {
+        writeln!(out, "  close_code: {}", code)?;
+    }
+    if let Some(reason) = &diag.close_reason {
+        writeln!(out, "  close_reason: \"{}\"", reason.replace('"', "\\\""))?;
+    }
+    if let Some(id) = &diag.second_publisher_connection_id {
+        writeln!(out, "  second_publisher_connection_id: {}", id)?;
+    }
+    if let Some(id) = &diag.second_subscriber_connection_id {
+        writeln!(out, "  second_subscriber_connection_id: {}", id)?;
+    }
+    if let Some(received) = diag.second_subscriber_received {
+        writeln!(out, "  second_subscriber_received: {}", received)?;
+    }
+    if let Some(count) = diag.subscriptions_succeeded {
+        writeln!(out, "  subscriptions_succeeded: {}", count)?;
+    }
+    if let Some(limit) = diag.subscription_limit {
+        writeln!(out, "  subscription_limit: {}", limit)?;
+    }
+    if let Some(state) = diag.resubscribe {
+        writeln!(out, "  resubscribe: {}", state)?;
+    }
+    if let Some(policy) = diag.namespace_conflict {
+        writeln!(out, "  namespace_conflict: {}", policy)?;
+    }
+    if let Some(outcome) = diag.malformed_namespace {
+        writeln!(out, "  malformed_namespace: {}", outcome)?;
+    }
+    if let Some(err) = &diag.malformed_namespace_error {
+        writeln!(
+            out,
+            "  malformed_namespace_error: \"{}\"",
+            err.replace('"', "\\\"")
+        )?;
+    }
+    if let Some(duplicate) = diag.duplicate_subscribe {
+        writeln!(out, "  duplicate_subscribe: {}", duplicate)?;
+    }
+    if let Some(early) = diag.early_subscription {
+        writeln!(out, "  early_subscription: {}", early)?;
+    }
+    if let Some(order) = &diag.group_order {
+        writeln!(out, "  group_order: {}", order)?;
+    }
+    if let Some(count) = diag.out_of_order {
+        writeln!(out, "  out_of_order: {}", count)?;
+    }
+    if let Some(path) = &diag.announced_path {
+        writeln!(out, "  announced_path: {}", path)?;
+    }
+    if let Some(preserved) = diag.names_preserved {
+        writeln!(out, "  names_preserved: {}", preserved)?;
+    }
+    if let Some(authenticated) = diag.authenticated {
+        writeln!(out, "  authenticated: {}", authenticated)?;
+    }
+    if let Some(objects) = &diag.track_objects {
+        writeln!(out, "  track_objects: {}", objects)?;
+    }
+    if let Some(expected) = diag.objects_expected {
+        writeln!(out, "  objects_expected: {}", expected)?;
+    }
+    if let Some(delivered) = diag.objects_delivered {
+        writeln!(out, "  objects_delivered: {}", delivered)?;
+    }
+    if let Some(gaps) = diag.gaps_detected {
+        writeln!(out, "  gaps_detected: {}", gaps)?;
+    }
+    if let Some(gap) = diag.max_interarrival_ms {
+        writeln!(out, "  max_interarrival_ms: {}", gap)?;
+    }
+    if let Some(buffered) = diag.max_buffered_bytes {
+        writeln!(out, "  max_buffered_bytes: {}", buffered)?;
+    }
+    if let Some(backlog) = diag.publisher_backlog_bytes {
+        writeln!(out, "  publisher_backlog_bytes: {}", backlog)?;
+    }
+    if let Some(version) = &diag.setup_version {
+        writeln!(out, "  setup_version: {}", version)?;
+    }
+    if let Some(parameters) = &diag.setup_parameters {
+        writeln!(out, "  setup_parameters: {}", parameters)?;
+    }
+    if let Some(max) = diag.max_request_id {
+        writeln!(out, "  max_request_id: {}", max)?;
+    }
+    if let Some(implementation) = &diag.implementation {
+        writeln!(out, "  implementation: {}", implementation)?;
+    }
+    if let Some(transitions) = diag.announce_transitions {
+        writeln!(out, "  announce_transitions: {}", transitions)?;
+    }
+    if let Some(ms) = diag.discovery_ms {
+        writeln!(out, "  discovery_ms: {}", ms)?;
+    }
+    if let Some(count) = diag.announced_count {
+        writeln!(out, "  announced_count: {}", count)?;
+    }
+    if let Some(survived) = diag.session_survived {
+        writeln!(out, "  session_survived: {}", survived)?;
+    }
+    if let Some(complete) = diag.subscribers_complete {
+        writeln!(out, "  subscribers_complete: {}", complete)?;
+    }
+    if let Some(matched) = diag.checksum_match {
+        writeln!(out, "  checksum_match: {}", matched)?;
+    }
+    if let Some(reannounce) = diag.reannounce {
+        writeln!(out, "  reannounce: {}", reannounce)?;
+    }
+    if let Some(err) = &diag.subscribe_error {
+        writeln!(out, "  subscribe_error: \"{}\"", err.replace('"', "\\\""))?;
+    }
+    writeln!(out, "  ...")?;
+    Ok(())
+}
+
+fn print_failure_diagnostics(out: &mut impl Write, report: &TestReport) -> std::io::Result<()> {
+    let message = report.message.as_deref().unwrap_or_default();
+    writeln!(out, "  ---")?;
+    writeln!(out, "  duration_ms: {}", report.duration_ms)?;
+    print_run_stats(out, report)?;
+    if let Some(kind) = report.failure_kind {
+        writeln!(out, "  failure_kind: {}", kind.as_str())?;
+    }
+    writeln!(out, "  message: \"{}\"", message.replace('"', "\\\""))?;
+    if let Some(chain) = &report.error_chain {
+        writeln!(out, "  backtrace:")?;
+        for cause in chain {
+            writeln!(out, "    - \"{}\"", cause.replace('"', "\\\""))?;
+        }
+    }
+    writeln!(out, "  ...")?;
+    Ok(())
+}
+
+/// Everything needed to run a test, shared by every test run against the same relays.
+#[derive(Clone)]
+struct Runner {
+    client: connect::Client,
+    /// A client verifying certificates even under `--tls-disable-verify`, with `--tls-verify-test`.
+    verifying_client: Option<connect::Client>,
+    /// The session shared by `REUSABLE_TESTS`, one test at a time.
+    shared: Option<std::sync::Arc<tokio::sync::Mutex<SharedSession>>>,
+    relay_urls: Vec<url::Url>,
+    namespace: String,
+    track: String,
+    track_count: u8,
+    payload_bytes: u64,
+    object_bytes: u64,
+    /// The `--payload-file` contents, read once up front.
+    payload: Option<bytes::Bytes>,
+    chunk_bytes: usize,
+    subscribers: u8,
+    max_subscriptions: u32,
+    churn_cycles: u32,
+    announce_count: u32,
+    /// `--seed`, or the random one picked in its place.
+    seed: u64,
+    timeouts: Timeouts,
+    repeat: usize,
+    warmup: bool,
+    verbose_errors: bool,
+    retry_timeouts: u32,
+    /// `--before` and `--after` hooks, as (test, command).
+    before: Vec<(String, String)>,
+    after: Vec<(String, String)>,
+}
+
+/// Per-test timeouts: the base timeout scaled by `--timeout-scale`, unless
+/// `--timeout-override` sets one explicitly.
+#[derive(Clone)]
+struct Timeouts {
+    scale: f64,
+    overrides: Vec<(String, Duration)>,
+    /// `--idle-secs`, which `idle-session` waits out on top of its own timeout.
+    idle: Duration,
+    /// `--inject-delay-ms`, likewise for `announce-subscribe`.
+    inject_delay: Duration,
+}
+
+impl Timeouts {
+    fn get(&self, name: &str) -> Duration {
+        // Later overrides win, matching how repeated flags usually behave.
+        match self.overrides.iter().rev().find(|(test, _)| test == name) {
+            Some((_, timeout)) => *timeout,
+            None => {
+                let timeout = base_timeout(name).mul_f64(self.scale);
+                // Pauses are asked for, not waited on, so they aren't scaled.
+                match name {
+                    "idle-session" => timeout + self.idle,
+                    "announce-subscribe" => timeout + self.inject_delay,
+                    _ => timeout,
+                }
+            }
+        }
+    }
+}
+
+fn base_timeout(name: &str) -> Duration {
+    match name {
+        "setup-only" => Duration::from_secs(2),
+        "setup-only-rawquic" => Duration::from_secs(2),
+        "announce-only" => Duration::from_secs(2),
+        "publish-namespace-done" => Duration::from_secs(2),
+        "announce-subscribe" => Duration::from_secs(3),
+        "subscribe-then-unannounce" => Duration::from_secs(3),
+        "data-roundtrip" => Duration::from_secs(3),
+        "cross-relay-subscribe" => Duration::from_secs(5),
+        "goaway-handling" => Duration::from_secs(5),
+        "multi-track-announce" => Duration::from_secs(3),
+        "priority-ordering" => Duration::from_secs(5),
+        "subscribe-missing-track" => Duration::from_secs(3),
+        "throughput-benchmark" => Duration::from_secs(10),
+        "reconnect" => Duration::from_secs(4),
+        "close-codes" => Duration::from_secs(5),
+        "re-announce" => Duration::from_secs(6),
+        "large-object" => Duration::from_secs(5),
+        "fanout" => Duration::from_secs(5),
+        // Plus --idle-secs, added in `Timeouts::get`.
+        "idle-session" => Duration::from_secs(3),
+        "prefix-announce" => Duration::from_secs(3),
+        "group-ordering" => Duration::from_secs(3),
+        "subscribe-before-track" => Duration::from_secs(4),
+        "duplicate-subscribe" => Duration::from_secs(3),
+        "malformed-namespace" => Duration::from_secs(5),
+        "namespace-conflict" => Duration::from_secs(5),
+        "resubscribe" => Duration::from_secs(5),
+        "max-subscriptions" => Duration::from_secs(30),
+        "setup-only-ipv6" => Duration::from_secs(2),
+        "time-to-first-object" => Duration::from_secs(5),
+        "zero-rtt-resume" => Duration::from_secs(4),
+        "relay-initiated-close" => Duration::from_secs(4),
+        "backlog-announce" => Duration::from_secs(3),
+        "subscribe-abort" => Duration::from_secs(5),
+        "authenticated-announce" => Duration::from_secs(3),
+        "announce-churn" => Duration::from_secs(5),
+        "impaired-delivery" => Duration::from_secs(10),
+        "object-boundary" => Duration::from_secs(5),
+        "interleaved-tracks" => Duration::from_secs(5),
+        "bulk-announce" => Duration::from_secs(10),
+        "tls-verify" => Duration::from_secs(3),
+        "priority-delivery" => Duration::from_secs(5),
+        "live-stream" => Duration::from_secs(10),
+        "binary-names" => Duration::from_secs(3),
+        "backpressure" => Duration::from_secs(10),
+        "setup-params" => Duration::from_secs(3),
+        _ => Duration::from_secs(5),
+    }
+}
+
+async fn run_test(name: &str, runner: &Runner, shared: Option<&SharedSession>) -> TestOutcome {
+    let timeout = runner.timeouts.get(name);
+
+    let inner = run_test_inner(name, runner, shared);
+    let result =
+        match tokio::time::timeout(timeout, inner).await {
+            Ok(result) => result,
+            Err(elapsed) => Err(anyhow::Error::new(elapsed)
+                .context(format!("timeout after {}ms", timeout.as_millis()))),
+        };
+
+    match result {
+        Ok(diagnostics) => TestOutcome {
+            status: Status::Passed,
+            diagnostics,
+            failure: None,
+        },
+        Err(err) => TestOutcome {
+            status: Status::Failed,
+            diagnostics: Diagnostics::default(),
+            failure: Some(FailureInfo {
+                kind: FailureKind::classify(&err),
+                message: format!("{:#}", err),
+                chain: err.chain().map(ToString::to_string).collect(),
+            }),
+        },
+    }
+}
+
+/// What an individual test function needs: where to connect and what to publish.
+struct TestContext<'a> {
+    client: &'a connect::Client,
+    verifying_client: Option<&'a connect::Client>,
+    relay_url: &'a url::Url,
+    namespace: &'a str,
+    track: &'a str,
+    track_count: u8,
+    payload_bytes: u64,
+    object_bytes: u64,
+    payload: Option<&'a bytes::Bytes>,
+    chunk_bytes: usize,
+    subscribers: u8,
+    max_subscriptions: u32,
+    churn_cycles: u32,
+    announce_count: u32,
+    seed: u64,
+    idle: Duration,
+    inject_delay: Duration,
+    shared: Option<&'a SharedSession>,
+}
+
+/// The session `--reuse-connection` keeps open across tests, along with the
+/// origin it publishes, since that's fixed when connecting.
+struct SharedSession {
+    conn: connect::Connection,
+    origin: OriginProducer,
+}
+
+/// The session a single-session test runs over.
+enum TestSession<'a> {
+    Owned(connect::Connection),
+    Shared(&'a connect::Connection),
+}
+
+impl TestSession<'_> {
+    fn diagnostics(&self) -> Diagnostics {
+        match self {
+            Self::Owned(conn) => Diagnostics::single(conn),
+            // The shared session's counters include every test before this one.
+            Self::Shared(conn) => Diagnostics {
+                shared_connection: Some(true),
+                bytes_sent: None,
+                bytes_received: None,
+                ..Diagnostics::single(conn)
+            },
+        }
+    }
+
+    /// Close the session, unless it's shared with other tests.
+    fn close(self) {
+        if let Self::Owned(mut conn) = self {
+            conn.close(moq_lite::Error::Cancel);
+        }
+    }
+}
+
+/// The publisher and subscriber sessions of a two-connection test.
+struct SessionPair {
+    publisher: connect::Connection,
+    subscriber: connect::Connection,
+}
+
+impl SessionPair {
+    fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::pub_sub(&self.publisher, &self.subscriber)
+    }
+
+    fn close(mut self) {
+        self.publisher.close(moq_lite::Error::Cancel);
+        self.subscriber.close(moq_lite::Error::Cancel);
+    }
+}
+
+impl<'a> TestContext<'a> {
+    /// A generator for test data, starting over from `--seed` each time so a
+    /// test's data doesn't depend on what ran before it.
+    fn rng(&self) -> fastrand::Rng {
+        fastrand::Rng::with_seed(self.seed)
+    }
+
+    /// Where a single-session test should publish: the shared session's origin
+    /// if there is one, otherwise a fresh one of its own.
+    fn origin(&self) -> OriginProducer {
+        match self.shared {
+            Some(shared) => shared.origin.clone(),
+            None => Origin::produce(),
+        }
+    }
+
+    /// The shared session if there is one, otherwise a new session publishing `origin`.
+    async fn session(&self, origin: Option<&OriginProducer>) -> anyhow::Result<TestSession<'a>> {
+        if let Some(shared) = self.shared {
+            return Ok(TestSession::Shared(&shared.conn));
+        }
+
+        let client = match origin {
+            Some(origin) => self.client.clone().with_publish(origin.consume()),
+            None => self.client.clone(),
+        };
+        let conn = client
+            .connect(self.relay_url.clone())
+            .await
+            .context("failed to connect")?;
+        Ok(TestSession::Owned(conn))
+    }
+
+    /// Connect a publisher serving `publish` to `url`, then `delay` later a
+    /// subscriber delivering announcements into `consume`.
+    async fn session_pair(
+        &self,
+        url: &url::Url,
+        publish: &OriginProducer,
+        consume: OriginProducer,
+        delay: Duration,
+    ) -> anyhow::Result<SessionPair> {
+        self.session_pair_between(url, url, publish, consume, delay)
+            .await
+    }
+
+    /// Like `session_pair`, but with the subscriber on `sub_url`, which may be
+    /// another relay.
+    async fn session_pair_between(
+        &self,
+        pub_url: &url::Url,
+        sub_url: &url::Url,
+        publish: &OriginProducer,
+        consume: OriginProducer,
+        delay: Duration,
+    ) -> anyhow::Result<SessionPair> {
+        let publisher = self
+            .client
+            .clone()
+            .with_publish(publish.consume())
+            .connect(pub_url.clone())
+            .await
+            .context("publisher failed to connect")?;
+
+        tokio::time::sleep(delay).await;
+
+        let subscriber = self
+            .client
+            .clone()
+            .with_consume(consume)
+            .connect(sub_url.clone())
+            .await
+            .context("subscriber failed to connect")?;
+
+        Ok(SessionPair {
+            publisher,
+            subscriber,
+        })
+    }
+}
+
+async fn run_test_inner(
+    name: &str,
+    runner: &Runner,
+    shared: Option<&SharedSession>,
+) -> anyhow::Result<Diagnostics> {
+    let ctx = TestContext {
+        client: &runner.client,
+        verifying_client: runner.verifying_client.as_ref(),
+        relay_url: &runner.relay_urls[0],
+        namespace: &runner.namespace,
+        track: &runner.track,
+        track_count: runner.track_count,
+        payload_bytes: runner.payload_bytes,
+        object_bytes: runner.object_bytes,
+        payload: runner.payload.as_ref(),
+        chunk_bytes: runner.chunk_bytes,
+        subscribers: runner.subscribers,
+        max_subscriptions: runner.max_subscriptions,
+        churn_cycles: runner.churn_cycles,
+        announce_count: runner.announce_count,
+        seed: runner.seed,
+        idle: runner.timeouts.idle,
+        inject_delay: runner.timeouts.inject_delay,
+        shared,
+    };
+
+    match name {
+        "setup-only" => test_setup_only(&ctx).await,
+        "setup-only-rawquic" => test_setup_only_rawquic(&ctx).await,
+        "announce-only" => test_announce_only(&ctx).await,
+        "publish-namespace-done" => test_publish_namespace_done(&ctx).await,
+        "announce-subscribe" => test_announce_subscribe(&ctx).await,
+        "subscribe-then-unannounce" => test_subscribe_then_unannounce(&ctx).await,
+        "data-roundtrip" => test_data_roundtrip(&ctx).await,
+        "cross-relay-subscribe" => test_cross_relay_subscribe(&ctx, &runner.relay_urls[1]).await,
+        "goaway-handling" => test_goaway_handling(&ctx).await,
+        "multi-track-announce" => test_multi_track_announce(&ctx).await,
+        "priority-ordering" => test_priority_ordering(&ctx).await,
+        "subscribe-missing-track" => test_subscribe_missing_track(&ctx).await,
+        "throughput-benchmark" => test_throughput_benchmark(&ctx).await,
+        "reconnect" => test_reconnect(&ctx).await,
+        "close-codes" => test_close_codes(&ctx).await,
+        "re-announce" => test_re_announce(&ctx).await,
+        "large-object" => test_large_object(&ctx).await,
+        "fanout" => test_fanout(&ctx).await,
+        "idle-session" => test_idle_session(&ctx).await,
+        "prefix-announce" => test_prefix_announce(&ctx).await,
+        "group-ordering" => test_group_ordering(&ctx).await,
+        "subscribe-before-track" => test_subscribe_before_track(&ctx).await,
+        "duplicate-subscribe" => test_duplicate_subscribe(&ctx).await,
+        "malformed-namespace" => test_malformed_namespace(&ctx).await,
+        "namespace-conflict" => test_namespace_conflict(&ctx).await,
+        "resubscribe" => test_resubscribe(&ctx).await,
+        "max-subscriptions" => test_max_subscriptions(&ctx).await,
+        "setup-only-ipv6" => test_setup_only_ipv6(&ctx).await,
+        "time-to-first-object" => test_time_to_first_object(&ctx).await,
+        "zero-rtt-resume" => test_zero_rtt_resume(&ctx).await,
+        "relay-initiated-close" => test_relay_initiated_close(&ctx).await,
+        "backlog-announce" => test_backlog_announce(&ctx).await,
+        "subscribe-abort" => test_subscribe_abort(&ctx).await,
+        "authenticated-announce" => test_authenticated_announce(&ctx).await,
+        "announce-churn" => test_announce_churn(&ctx).await,
+        "impaired-delivery" => test_impaired_delivery(&ctx).await,
+        "object-boundary" => test_object_boundary(&ctx).await,
+        "interleaved-tracks" => test_interleaved_tracks(&ctx).await,
+        "bulk-announce" => test_bulk_announce(&ctx).await,
+        "tls-verify" => test_tls_verify(&ctx).await,
+        "priority-delivery" => test_priority_delivery(&ctx).await,
+        "live-stream" => test_live_stream(&ctx).await,
+        "binary-names" => test_binary_names(&ctx).await,
+        "backpressure" => test_backpressure(&ctx).await,
+        "setup-params" => test_setup_params(&ctx).await,
+        _ => anyhow::bail!("unknown test: {}", name),
+    }
+}
+
+/// The `index`th track in `multi-track-announce`, each with a distinct priority.
+fn multi_track(index: u8) -> Track {
+    let name = match MULTI_TRACK_NAMES.get(index as usize) {
+        Some(name) => name.to_string(),
+        None => format!("track-{}", index),
+    };
+    Track {
+        name,
+        priority: index,
+    }
+}
+
+/// Wait for the relay to announce the broadcast at `namespace`, ignoring any
+/// other broadcasts it happens to carry.
+async fn wait_for_broadcast(
+    consumer: &mut OriginConsumer,
+    namespace: &str,
+) -> anyhow::Result<BroadcastConsumer> {
+    loop {
+        match consumer.announced().await.context("consumer closed")? {
+            (path, Some(broadcast)) if path.as_str() == namespace => return Ok(broadcast),
+            (path, None) if path.as_str() == namespace => {
+                anyhow::bail!("unexpected unannouncement: {}", path)
+            }
+            (path, _) => tracing::debug!(%path, "ignoring unrelated announcement"),
+        }
+    }
+}
+
+/// Wait up to `window` for the relay to announce every broadcast in `paths`,
+/// in any order, naming the first few still missing if it doesn't.
+async fn wait_for_broadcasts(
+    consumer: &mut OriginConsumer,
+    paths: &[String],
+    window: Duration,
+) -> anyhow::Result<()> {
+    let mut missing: std::collections::HashSet<&str> = paths.iter().map(String::as_str).collect();
+    let drain = async {
+        while !missing.is_empty() {
+            match consumer.announced().await.context("consumer closed")? {
+                (path, Some(_)) => {
+                    if !missing.remove(path.as_str()) {
+                        tracing::debug!(%path, "ignoring unrelated announcement");
+                    }
+                }
+                (path, None) if missing.contains(path.as_str()) => {
+                    anyhow::bail!("unexpected unannouncement: {}", path)
+                }
+                (path, None) => tracing::debug!(%path, "ignoring unrelated unannouncement"),
+            }
+        }
+        anyhow::Ok(())
+    };
+    match tokio::time::timeout(window, drain).await {
+        Ok(res) => res,
+        Err(_) => {
+            let mut missing: Vec<&str> = missing.into_iter().collect();
+            missing.sort();
+            let mut named = missing[..missing.len().min(MISSING_LISTED)].join(", ");
+            if missing.len() > MISSING_LISTED {
+                named += &format!(" and {} more", missing.len() - MISSING_LISTED);
+            }
+            anyhow::bail!(
+                "discovered {} of {} broadcasts; missing: {}",
+                paths.len() - missing.len(),
+                paths.len(),
+                named
+            );
+        }
+    }
+}
+
+/// Wait for the publisher to be asked for every one of `tracks`, returning
+/// their producers in the same order, however the requests arrived.
+async fn requested_tracks<const N: usize>(
+    dynamic: &mut BroadcastDynamic,
+    tracks: [&Track; N],
+) -> anyhow::Result<[TrackProducer; N]> {
+    let mut requested: [Option<TrackProducer>; N] = std::array::from_fn(|_| None);
+    while requested.iter().any(Option::is_none) {
+        let pub_track = dynamic
+            .requested_track()
+            .await
+            .context("publisher broadcast closed")?;
+        let name = &pub_track.info.name;
+        let i = tracks
+            .iter()
+            .position(|track| &track.name == name)
+            .with_context(|| {
+                format!("publisher received request for unexpected track: {}", name)
+            })?;
+        anyhow::ensure!(
+            requested[i].is_none(),
+            "publisher received a second request for track: {}",
+            name
+        );
+        requested[i] = Some(pub_track);
+    }
+    Ok(requested.map(|track| track.expect("every track was requested")))
+}
+
+/// Connect via WebTransport, complete handshake, close session.
+async fn test_setup_only(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let session = ctx.session(None).await?;
+    let diagnostics = session.diagnostics();
+    session.close();
+
+    Ok(diagnostics)
+}
+
+/// Like `setup-only`, but forcing raw QUIC (`moqt://`) instead of WebTransport.
+async fn test_setup_only_rawquic(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let url = connect::raw_quic_url(ctx.relay_url)?;
+
+    let mut session = ctx
+        .client
+        .clone()
+        .connect(url)
+        .await
+        .context("failed to connect")?;
+
+    anyhow::ensure!(
+        !session.is_webtransport(),
+        "negotiated WebTransport instead of raw QUIC"
+    );
+
+    let diagnostics = Diagnostics::single(&session);
+    session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Connect, publish broadcast at test namespace, wait for acknowledgment.
+async fn test_announce_only(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let origin = ctx.origin();
+
+    // Create broadcast before connecting
+    let broadcast = Broadcast::produce();
+    origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let session = ctx.session(Some(&origin)).await?;
+
+    // Wait briefly for the announce to be processed
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let diagnostics = session.diagnostics();
+    session.close();
+
+    Ok(diagnostics)
+}
+
+/// Connect, publish broadcast, then close/drop the broadcast.
+async fn test_publish_namespace_done(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let origin = ctx.origin();
+
+    let broadcast = Broadcast::produce();
+    origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let session = ctx.session(Some(&origin)).await?;
+
+    // Wait for announce to be processed, then drop the broadcast (unpublish)
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    drop(broadcast);
+
+    // Wait briefly for the done to propagate
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let diagnostics = session.diagnostics();
+    session.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces, subscriber subscribes.
+async fn test_announce_subscribe(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Serve tracks on demand so we can observe the subscription reaching the publisher
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    // moq-lite does not expose an announce-acknowledged future (moq-lite has no
+    // announce ack and PUBLISH_NAMESPACE_OK is consumed internally), so there is
+    // nothing to wait on between the two connections. Instead the subscriber waits
+    // for the relay to forward the announcement, which can only happen once it has
+    // been processed. --inject-delay-ms holds the subscriber back, to sweep for races.
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, ctx.inject_delay)
+        .await?;
+
+    // Wait for the relay to announce the published broadcast. A relay that
+    // dropped the subscriber and one that never forwards announcements both
+    // look like a hang, so tell them apart by whether the session survived.
+    let sub_broadcast = tokio::select! {
+        announced = tokio::time::timeout(
+            ANNOUNCE_WINDOW,
+            wait_for_broadcast(&mut sub_consumer, ctx.namespace),
+        ) => match announced {
+            Ok(Ok(broadcast)) => broadcast,
+            Ok(Err(err)) if sessions.subscriber.is_closed() => {
+                return Err(err.context("announce_stream_closed: relay closed the subscriber session"));
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(_) => anyhow::bail!(
+                "announce_never_arrived: subscriber session open, but no announcement of {} within {}ms",
+                ctx.namespace,
+                ANNOUNCE_WINDOW.as_millis()
+            ),
+        },
+        err = sessions.subscriber.closed() => {
+            return Err(anyhow::Error::new(err)
+                .context("announce_stream_closed: relay closed the subscriber session before announcing"));
+        }
+    };
+
+    // Now subscribe to a track on the announced broadcast
+    let track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    // Wait for the relay to route the subscription to the publisher
+    let _requested = tokio::select! {
+        requested = dynamic.requested_track() => {
+            let requested = requested.context("publisher broadcast closed")?;
+            anyhow::ensure!(
+                requested.info.name == ctx.track,
+                "publisher received request for unexpected track: {}",
+                requested.info.name
+            );
+            requested
+        }
+        result = track.closed() => {
+            result.context("track closed")?;
+            anyhow::bail!("track closed before reaching publisher");
+        }
+    };
+
+    let diagnostics = sessions.diagnostics();
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: subscriber subscribes, then the publisher drops the broadcast
+/// and the relay must end the subscription.
+async fn test_subscribe_then_unannounce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    // Only tear down once the subscription is live end to end
+    let pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    // Dropping every producer unannounces the broadcast and resets the track
+    drop(pub_track);
+    drop(dynamic);
+    drop(broadcast);
+
+    // Either a clean SUBSCRIBE_DONE or a reset is fine; a transport error means the
+    // relay tore down the whole subscriber session instead of just the subscription.
+    if let Err(moq_lite::Error::Transport) = track.closed().await {
+        anyhow::bail!("subscriber session failed instead of the subscription ending");
+    }
+
+    let diagnostics = sessions.diagnostics();
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes a group of frames, subscriber reads them back.
+///
+/// Each frame carries its send time, so the subscriber can report per-frame
+/// latency. Both ends run in this process and read the same monotonic clock,
+/// so there's no skew to correct for.
+async fn test_data_roundtrip(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let epoch = Instant::now();
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Only write the group once the subscription has reached the publisher,
+    // so the relay doesn't have a chance to miss it.
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+    anyhow::ensure!(
+        pub_track.info.name == ctx.track,
+        "publisher received request for unexpected track: {}",
+        pub_track.info.name
+    );
+
+    let mut pub_group = pub_track.append_group().context("failed to create group")?;
+    for frame in TEST_FRAMES {
+        let sent = epoch.elapsed().as_micros() as u64;
+        let mut stamped = sent.to_be_bytes().to_vec();
+        stamped.extend_from_slice(frame);
+        pub_group
+            .write_frame(stamped)
+            .context("failed to write frame")?;
+    }
+    pub_group.finish().context("failed to finish group")?;
+
+    // Read the group back and compare it frame by frame
+    let mut group = track
+        .next_group()
+        .await
+        .context("failed to read group")?
+        .context("track ended before receiving a group")?;
+
+    let mut bytes_transferred = 0;
+    let mut latencies = Vec::with_capacity(TEST_FRAMES.len());
+    for (index, expected) in TEST_FRAMES.iter().enumerate() {
+        let frame = group
+            .read_frame()
+            .await
+            .context("failed to read frame")?
+            .with_context(|| {
+                format!(
+                    "group ended after {} of {} frames",
+                    index,
+                    TEST_FRAMES.len()
+                )
+            })?;
+        let received = epoch.elapsed();
+
+        anyhow::ensure!(
+            frame.len() >= SEND_STAMP_LEN,
+            "frame {} too short for a timestamp: {} bytes",
+            index,
+            frame.len()
+        );
+        let (stamp, payload) = frame.split_at(SEND_STAMP_LEN);
+        anyhow::ensure!(
+            payload == *expected,
+            "frame {} mismatch: expected {:02x?}, got {:02x?}",
+            index,
+            expected,
+            payload
+        );
+        let sent = Duration::from_micros(u64::from_be_bytes(stamp.try_into().unwrap()));
+        latencies.push(received.saturating_sub(sent));
+        bytes_transferred += frame.len() as u64;
+    }
+    latencies.sort();
+
+    if group
+        .read_frame()
+        .await
+        .context("failed to read frame")?
+        .is_some()
+    {
+        anyhow::bail!("group contained more than {} frames", TEST_FRAMES.len());
+    }
+
+    let diagnostics = Diagnostics {
+        bytes_transferred: Some(bytes_transferred),
+        latency_p50_ms: Some(millis(percentile(&latencies, 50))),
+        latency_p99_ms: Some(millis(percentile(&latencies, 99))),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Publisher connects to the first relay, subscriber to the second; the
+/// announcement must be forwarded between them.
+async fn test_cross_relay_subscribe(
+    ctx: &TestContext<'_>,
+    sub_relay_url: &url::Url,
+) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Subscriber setup, on the other relay
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair_between(
+            ctx.relay_url,
+            sub_relay_url,
+            &pub_origin,
+            sub_origin,
+            Duration::ZERO,
+        )
+        .await?;
+
+    // Wait for the second relay to announce the broadcast published on the first
+    wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let diagnostics = sessions.diagnostics();
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Connect and hold the session open, watching for the relay to send GOAWAY.
+///
+/// moq-lite doesn't implement GOAWAY: on receiving one it fails the session with
+/// `Unsupported` and closes the connection itself, and `Session::closed` carries
+/// no reason. So the only thing we can observe is the connection being closed
+/// locally while the test still holds it, which moq-lite also does on any other
+/// protocol error it can't handle. That is reported as `local_close` rather than
+/// claimed as a GOAWAY. Relays that never send GOAWAY pass as long as the
+/// session stays up for the whole window.
+async fn test_goaway_handling(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    use moq_native::web_transport_quinn::quinn::ConnectionError;
+
+    let mut session = ctx
+        .client
+        .clone()
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect")?;
+
+    let local_close = tokio::select! {
+        err = session.closed() => match err {
+            ConnectionError::LocallyClosed => true,
+            err => anyhow::bail!("session closed unexpectedly: {}", err),
+        },
+        _ = tokio::time::sleep(GOAWAY_WINDOW) => false,
+    };
+
+    let diagnostics = Diagnostics {
+        local_close: Some(local_close),
+        ..Diagnostics::single(&session)
+    };
+    session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces one broadcast carrying several tracks,
+/// and the subscriber must be able to subscribe to and receive from all of them.
+async fn test_multi_track_announce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let tracks: Vec<Track> = (0..ctx.track_count).map(multi_track).collect();
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Tracks are served on demand, so each one is only written once its
+    // subscription has reached the publisher.
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut sub_tracks = Vec::with_capacity(tracks.len());
+    for track in &tracks {
+        let sub_track = sub_broadcast
+            .subscribe_track(track)
+            .with_context(|| format!("failed to subscribe to track: {}", track.name))?;
+        sub_tracks.push(sub_track);
+    }
+
+    // Answer each request with a single group carrying the track's own name,
+    // holding on to the producers so the tracks stay open until we're done.
+    let publish = async {
+        let mut served = Vec::with_capacity(tracks.len());
+        while served.len() < tracks.len() {
+            let mut pub_track = dynamic
+                .requested_track()
+                .await
+                .context("publisher broadcast closed")?;
+            let name = pub_track.info.name.clone();
+            anyhow::ensure!(
+                tracks.iter().any(|track| track.name == name),
+                "publisher received request for unexpected track: {}",
+                name
+            );
+            anyhow::ensure!(
+                !served.iter().any(|t: &TrackProducer| t.info.name == name),
+                "publisher received a second request for track: {}",
+                name
+            );
+
+            let mut group = pub_track.append_group().context("failed to create group")?;
+            group
+                .write_frame(name.into_bytes())
+                .context("failed to write frame")?;
+            group.finish().context("failed to finish group")?;
+            served.push(pub_track);
+        }
+        Ok(served)
+    };
+
+    let receive = async {
+        for (track, sub_track) in tracks.iter().zip(sub_tracks.iter_mut()) {
+            let frame = sub_track
+                .next_group()
+                .await
+                .with_context(|| format!("failed to read group on track: {}", track.name))?
+                .with_context(|| format!("track ended before receiving a group: {}", track.name))?
+                .read_frame()
+                .await
+                .with_context(|| format!("failed to read frame on track: {}", track.name))?
+                .with_context(|| format!("group ended without a frame on track: {}", track.name))?;
+            anyhow::ensure!(
+                frame.as_ref() == track.name.as_bytes(),
+                "track {} received data for another track: {:?}",
+                track.name,
+                String::from_utf8_lossy(&frame)
+            );
+        }
+        Ok(())
+    };
+
+    let (_served, ()) = tokio::try_join!(publish, receive)?;
+
+    let diagnostics = Diagnostics {
+        track_count: Some(ctx.track_count),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher floods a low- and a high-priority track, queueing
+/// all of the low-priority data first, and the relay should deliver the
+/// high-priority track ahead of it.
+///
+/// Delivery order is only guaranteed once the connection is congested, and even
+/// then groups already in flight arrive first, so this is checked loosely: the
+/// test passes if the first high-priority group arrives before half of the
+/// low-priority groups have.
+async fn test_priority_ordering(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // moq-lite serves higher values first.
+    let low = Track {
+        name: format!("{}-low", ctx.track),
+        priority: 1,
+    };
+    let high = Track {
+        name: format!("{}-high", ctx.track),
+        priority: 2,
+    };
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let sub_low = sub_broadcast
+        .subscribe_track(&low)
+        .context("failed to subscribe to low-priority track")?;
+    let sub_high = sub_broadcast
+        .subscribe_track(&high)
+        .context("failed to subscribe to high-priority track")?;
+
+    // Wait for both subscriptions before writing anything, so neither track
+    // gets a head start.
+    let [mut pub_low, mut pub_high] = requested_tracks(&mut dynamic, [&low, &high]).await?;
+
+    let frame = bytes::Bytes::from(vec![0u8; PRIORITY_FRAME_SIZE]);
+    for pub_track in [&mut pub_low, &mut pub_high] {
+        for _ in 0..PRIORITY_GROUPS {
+            let mut group = pub_track.append_group().context("failed to create group")?;
+            for _ in 0..PRIORITY_FRAMES {
+                group
+                    .write_frame(frame.clone())
+                    .context("failed to write frame")?;
+            }
+            group.finish().context("failed to finish group")?;
+        }
+    }
+
+    // Read every group concurrently, recording which track each belonged to as
+    // it finishes arriving; reading them in order would hide the real order.
+    let arrivals = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let receive = |mut track: TrackConsumer, label: &'static str| {
+        let arrivals = arrivals.clone();
+        async move {
+            let mut reading = tokio::task::JoinSet::new();
+            for _ in 0..PRIORITY_GROUPS {
+                let mut group = track
+                    .next_group()
+                    .await
+                    .context("failed to read group")?
+                    .with_context(|| format!("{} track ended early", label))?;
+                let arrivals = arrivals.clone();
+                reading.spawn(async move {
+                    while group.read_frame().await?.is_some() {}
+                    arrivals.lock().unwrap().push(label);
+                    anyhow::Ok(())
+                });
+            }
+
+            // Hold on to the track until every group is read; dropping it unsubscribes.
+            while let Some(result) = reading.join_next().await {
+                result
+                    .context("reader task panicked")?
+                    .context("failed to read frame")?;
+            }
+            anyhow::Ok(())
+        }
+    };
+    tokio::try_join!(receive(sub_low, "low"), receive(sub_high, "high"))?;
+
+    let arrivals = std::mem::take(&mut *arrivals.lock().unwrap());
+    let low_before_high = arrivals.iter().take_while(|label| **label == "low").count();
+
+    let diagnostics = Diagnostics {
+        priority_order: Some(arrivals.join(",")),
+        bytes_transferred: Some(
+            (2 * PRIORITY_GROUPS * PRIORITY_FRAMES * PRIORITY_FRAME_SIZE) as u64,
+        ),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    anyhow::ensure!(
+        low_before_high < PRIORITY_GROUPS / 2,
+        "{} of {} low-priority groups arrived before the first high-priority group",
+        low_before_high,
+        PRIORITY_GROUPS
+    );
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces a broadcast with only the test track,
+/// and a subscription to any other track on it must fail rather than hang.
+async fn test_subscribe_missing_track(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup, with a fixed set of tracks so unknown ones are rejected
+    let pub_origin = Origin::produce();
+    let mut broadcast = Broadcast::produce();
+    let _pub_track = broadcast
+        .create_track(Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to create track")?;
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let track = sub_broadcast
+        .subscribe_track(&Track {
+            name: MISSING_TRACK.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    // The subscription should end in an error; a transport error means the
+    // whole session went down instead of just the subscription.
+    let err = match track.closed().await {
+        Ok(()) => anyhow::bail!("subscription to a missing track ended without an error"),
+        Err(moq_lite::Error::Transport) => {
+            anyhow::bail!("subscriber session failed instead of the subscription")
+        }
+        Err(err) => err,
+    };
+
+    let diagnostics = Diagnostics {
+        subscribe_error: Some(err.to_string()),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher sends `--payload-bytes` over a single track, and
+/// we time it from the first frame written to the last byte received, leaving
+/// out connection setup and subscription.
+async fn test_throughput_benchmark(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    let start = Instant::now();
+
+    let frame = bytes::Bytes::from(vec![0u8; THROUGHPUT_FRAME_SIZE]);
+    let mut remaining = ctx.payload_bytes as usize;
+    while remaining > 0 {
+        let mut group = pub_track.append_group().context("failed to create group")?;
+        for _ in 0..THROUGHPUT_GROUP_FRAMES {
+            let size = remaining.min(THROUGHPUT_FRAME_SIZE);
+            group
+                .write_frame(frame.slice(..size))
+                .context("failed to write frame")?;
+            remaining -= size;
+            if remaining == 0 {
+                break;
+            }
+        }
+        group.finish().context("failed to finish group")?;
+    }
+
+    let mut received = 0;
+    while received < ctx.payload_bytes {
+        let mut group = track
+            .next_group()
+            .await
+            .context("failed to read group")?
+            .with_context(|| {
+                format!(
+                    "track ended after {} of {} bytes",
+                    received, ctx.payload_bytes
+                )
+            })?;
+        while let Some(frame) = group.read_frame().await.context("failed to read frame")? {
+            received += frame.len() as u64;
+        }
+    }
+
+    let elapsed = start.elapsed();
+    anyhow::ensure!(
+        received == ctx.payload_bytes,
+        "received {} bytes, expected {}",
+        received,
+        ctx.payload_bytes
+    );
+
+    let mbps = received as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0;
+    let diagnostics = Diagnostics {
+        bytes_transferred: Some(received),
+        throughput_mbps: Some((mbps * 1000.0).round() / 1000.0),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Connect, close with `Cancel`, then immediately connect again with a fresh
+/// session. The relay must accept the second handshake as if the first had
+/// never happened.
+async fn test_reconnect(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Both sessions consume announcements, since a relay may hang up on a
+    // session that neither publishes nor consumes anything.
+    let mut initial = ctx
+        .client
+        .clone()
+        .with_consume(Origin::produce())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect")?;
+    let initial_id = initial.id();
+    initial.close(moq_lite::Error::Cancel);
+
+    let mut reconnect = ctx
+        .client
+        .clone()
+        .with_consume(Origin::produce())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to reconnect")?;
+
+    // The session may be refused after the handshake, so check it's still up.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    anyhow::ensure!(
+        !reconnect.is_closed(),
+        "relay closed the reconnected session"
+    );
+
+    let diagnostics = Diagnostics {
+        initial_connection_id: Some(initial_id),
+        ..Diagnostics::single(&reconnect)
+    };
+    reconnect.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Open a session per entry in `CLOSE_ERRORS` and close each with that error,
+/// then check the relay still accepts (and keeps) a new session.
+async fn test_close_codes(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let mut closed = Vec::with_capacity(CLOSE_ERRORS.len());
+
+    for err in CLOSE_ERRORS {
+        let mut session = ctx
+            .client
+            .clone()
+            .with_consume(Origin::produce())
+            .connect(ctx.relay_url.clone())
+            .await
+            .with_context(|| format!("failed to connect session {}", closed.len() + 1))?;
+        closed.push(format!("{}={}", session.id(), err.to_code()));
+        session.close(err.clone());
+    }
+
+    let mut session = ctx
+        .client
+        .clone()
+        .with_consume(Origin::produce())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect after closing every session")?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    anyhow::ensure!(
+        !session.is_closed(),
+        "relay closed the session opened after the others"
+    );
+
+    let diagnostics = Diagnostics {
+        close_codes: Some(closed.join(",")),
+        ..Diagnostics::single(&session)
+    };
+    session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces, withdraws, and announces the same
+/// namespace again on the same session; the subscriber must see the second
+/// announcement and be able to subscribe through it.
+///
+/// Reports `reannounce: accepted` if the subscriber saw the namespace go away
+/// in between, or `coalesced` if the relay never forwarded the withdrawal.
+async fn test_re_announce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let first = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, first.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    // Withdraw the namespace and wait for the done to reach the subscriber
+    drop(first);
+    let unannounced = async {
+        loop {
+            match sub_consumer.announced().await {
+                Some((path, None)) if path.as_str() == ctx.namespace => return true,
+                Some((path, _)) => tracing::debug!(%path, "ignoring unrelated announcement"),
+                None => return false,
+            }
+        }
+    };
+    let unannounced = tokio::time::timeout(UNANNOUNCE_WINDOW, unannounced)
+        .await
+        .unwrap_or(false);
+
+    // Announce the same namespace again, on the same session
+    let second = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, second.consume());
+    let mut dynamic = second.dynamic();
+
+    // Subscribe through each announcement of the namespace until a subscription
+    // reaches the new broadcast; a relay may bounce the namespace a few times
+    // while it switches over. If the subscriber never saw the withdrawal, it
+    // still has the first announcement, which only works if the relay routes
+    // subscriptions through it to the new broadcast.
+    let track = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+    let subscribe = async {
+        let mut tracks = Vec::new();
+        if let Some(broadcast) = sub_consumer.consume_broadcast(ctx.namespace) {
+            tracks.extend(broadcast.subscribe_track(&track).ok());
+        }
+        loop {
+            match sub_consumer.announced().await {
+                Some((path, Some(broadcast))) if path.as_str() == ctx.namespace => {
+                    tracks.extend(broadcast.subscribe_track(&track).ok());
+                }
+                Some((path, _)) => tracing::debug!(%path, "ignoring announcement"),
+                None => return anyhow::anyhow!("consumer closed"),
+            }
+        }
+    };
+
+    tokio::select! {
+        requested = dynamic.requested_track() => {
+            requested.context("publisher broadcast closed")?;
+        }
+        err = subscribe => return Err(err),
+    }
+
+    let diagnostics = Diagnostics {
+        reannounce: Some(match unannounced {
+            true => "accepted",
+            false => "coalesced",
+        }),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes one `--object-bytes` object, subscriber
+/// reads it back and compares checksums. The object is far larger than a QUIC
+/// frame, so the relay has to buffer and forward it in pieces.
+///
+/// With `--payload-file`, the file goes instead, as a group of `--chunk-bytes`
+/// objects that the subscriber joins back together before comparing.
+async fn test_large_object(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    let (object, chunk_bytes) = match ctx.payload {
+        Some(payload) => (payload.clone(), ctx.chunk_bytes),
+        None => {
+            // Random bytes, so dropped or reordered chunks change the checksum.
+            let mut object = vec![0u8; ctx.object_bytes as usize];
+            ctx.rng().fill(&mut object);
+            let len = object.len();
+            (bytes::Bytes::from(object), len)
+        }
+    };
+    let sent = checksum(&object);
+
+    let mut group = pub_track.append_group().context("failed to create group")?;
+    for start in (0..object.len()).step_by(chunk_bytes) {
+        let end = (start + chunk_bytes).min(object.len());
+        group
+            .write_frame(object.slice(start..end))
+            .context("failed to write frame")?;
+    }
+    group.finish().context("failed to finish group")?;
+
+    let mut group = track
+        .next_group()
+        .await
+        .context("failed to read group")?
+        .context("track ended before the object arrived")?;
+    let mut frame = Vec::with_capacity(object.len());
+    while let Some(chunk) = group.read_frame().await.context("failed to read frame")? {
+        frame.extend_from_slice(&chunk);
+    }
+
+    anyhow::ensure!(
+        frame.len() == object.len(),
+        "received {} bytes, expected {}",
+        frame.len(),
+        object.len()
+    );
+    let received = checksum(&frame);
+    anyhow::ensure!(
+        received == sent,
+        "checksum mismatch: sent {:016x}, received {:016x}",
+        sent,
+        received
+    );
+
+    let diagnostics = Diagnostics {
+        bytes_transferred: Some(frame.len() as u64),
+        checksum_match: Some(true),
+        seed: ctx.payload.is_none().then_some(ctx.seed),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// 64-bit FNV-1a, enough to tell whether an object survived the relay intact.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// One publisher, `--subscribers` subscriber sessions: the publisher writes
+/// `TEST_FRAMES` as one group, and every subscriber must receive all of it.
+async fn test_fanout(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    let mut pub_session = ctx
+        .client
+        .clone()
+        .with_publish(pub_origin.consume())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("publisher failed to connect")?;
+
+    // Subscriber setup, one session each
+    let mut subscribers = Vec::with_capacity(ctx.subscribers as usize);
+    for index in 1..=ctx.subscribers {
+        let sub_origin = Origin::produce();
+        let mut sub_consumer = sub_origin.consume();
+
+        let session = ctx
+            .client
+            .clone()
+            .with_consume(sub_origin)
+            .connect(ctx.relay_url.clone())
+            .await
+            .with_context(|| format!("subscriber {} failed to connect", index))?;
+
+        let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+        let track = sub_broadcast
+            .subscribe_track(&Track {
+                name: ctx.track.to_string(),
+                priority: 0,
+            })
+            .with_context(|| format!("subscriber {} failed to subscribe", index))?;
+
+        subscribers.push((session, track));
+    }
+
+    // The relay forwards one subscription upstream however many it has, and
+    // serves the latest group to subscribers that join after it started.
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    let mut pub_group = pub_track.append_group().context("failed to create group")?;
+    for frame in TEST_FRAMES {
+        pub_group
+            .write_frame(frame.to_vec())
+            .context("failed to write frame")?;
+    }
+    pub_group.finish().context("failed to finish group")?;
+
+    // Each session buffers its own copy, so reading them in turn is fine.
+    let deadline = tokio::time::Instant::now() + FANOUT_WINDOW;
+    let mut complete = 0;
+    let mut failures = Vec::new();
+    for (index, (_, track)) in subscribers.iter_mut().enumerate() {
+        let read = async {
+            let mut group = track
+                .next_group()
+                .await
+                .context("failed to read group")?
+                .context("track ended before receiving a group")?;
+
+            let mut frames = Vec::new();
+            while let Some(frame) = group.read_frame().await.context("failed to read frame")? {
+                frames.push(frame);
+            }
+            anyhow::ensure!(
+                frames
+                    .iter()
+                    .map(|f| &f[..])
+                    .eq(TEST_FRAMES.iter().copied()),
+                "received {} frames that don't match the {} published",
+                frames.len(),
+                TEST_FRAMES.len()
+            );
+            Ok(())
+        };
+
+        match tokio::time::timeout_at(deadline, read).await {
+            Ok(Ok(())) => complete += 1,
+            Ok(Err(err)) => failures.push(format!("subscriber {}: {:#}", index + 1, err)),
+            Err(_) => failures.push(format!("subscriber {}: timed out", index + 1)),
+        }
+    }
+
+    anyhow::ensure!(
+        failures.is_empty(),
+        "{} of {} subscribers received the full group; {}",
+        complete,
+        ctx.subscribers,
+        failures.join("; ")
+    );
+
+    let ids: Vec<String> = subscribers
+        .iter()
+        .map(|(session, _)| session.id())
+        .collect();
+    let diagnostics = Diagnostics {
+        publisher_connection_id: Some(pub_session.id()),
+        subscriber_connection_ids: Some(ids.join(",")),
+        subscribers_complete: Some(complete),
+        ..Default::default()
+    };
+
+    pub_session.close(moq_lite::Error::Cancel);
+    for (mut session, _) in subscribers {
+        session.close(moq_lite::Error::Cancel);
+    }
+
+    Ok(diagnostics)
+}
+
+/// Connect and leave the session idle for `--idle-secs`, then announce on it;
+/// a second connection must see the announcement. Catches relays (or QUIC
+/// idle timers) that drop sessions with nothing in flight.
+async fn test_idle_session(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let origin = Origin::produce();
+
+    let mut session = ctx
+        .client
+        .clone()
+        .with_publish(origin.consume())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect")?;
+
+    let start = Instant::now();
+    tokio::select! {
+        err = session.closed() => anyhow::bail!(
+            "session closed after {}ms idle: {}",
+            start.elapsed().as_millis(),
+            err
+        ),
+        _ = tokio::time::sleep(ctx.idle) => {}
+    }
+
+    // Announce on the idle session, and check it reaches a fresh subscriber
+    let broadcast = Broadcast::produce();
+    origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let mut sub_session = ctx
+        .client
+        .clone()
+        .with_consume(sub_origin)
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("subscriber failed to connect")?;
+
+    wait_for_broadcast(&mut sub_consumer, ctx.namespace)
+        .await
+        .context("announce after idling never reached the subscriber")?;
+
+    let diagnostics = Diagnostics {
+        session_survived: Some(true),
+        ..Diagnostics::pub_sub(&session, &sub_session)
+    };
+
+    session.close(moq_lite::Error::Cancel);
+    sub_session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces one broadcast under a sub-namespace of
+/// the test namespace and one beside it, and a subscriber that only asked for
+/// the sub-namespace discovers the first and never the second.
+///
+/// moq-lite always asks the relay for every announcement, so the prefix is
+/// applied by the subscriber's origin; an unscoped consumer on the same origin
+/// confirms the sibling did arrive and was filtered out.
+async fn test_prefix_announce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let prefix = format!("{}/{}", ctx.namespace, PREFIX_ANNOUNCE_SCOPE);
+    let inside = format!("{}/{}", prefix, ctx.track);
+    let outside = format!("{}/{}", ctx.namespace, ctx.track);
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let inside_broadcast = Broadcast::produce();
+    let outside_broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(&inside, inside_broadcast.consume());
+    pub_origin.publish_broadcast(&outside, outside_broadcast.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut all_consumer = sub_origin.consume();
+    let mut scoped_consumer = sub_origin
+        .consume_only(&[prefix.as_str().into()])
+        .context("origin rejected the announce prefix")?;
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let announced_path = match scoped_consumer.announced().await {
+        Some((path, Some(_))) => path.to_string(),
+        Some((path, None)) => anyhow::bail!("unexpected unannouncement: {}", path),
+        None => anyhow::bail!("scoped consumer closed"),
+    };
+    anyhow::ensure!(
+        announced_path == inside,
+        "prefix {} discovered a broadcast outside it: {}",
+        prefix,
+        announced_path
+    );
+
+    // Once the unscoped consumer has seen the sibling, the scoped one has
+    // had its chance to (wrongly) see it too.
+    wait_for_broadcast(&mut all_consumer, &outside)
+        .await
+        .context("sibling broadcast never reached the subscriber")?;
+    if let Some((path, _)) = scoped_consumer.try_announced() {
+        anyhow::bail!(
+            "prefix {} discovered a broadcast outside it: {}",
+            prefix,
+            path
+        );
+    }
+
+    let diagnostics = Diagnostics {
+        announced_path: Some(announced_path),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes `GROUP_ORDERING_GROUPS` single-frame
+/// groups back to back, each carrying its own sequence number, and the
+/// subscriber must receive every one of them exactly once, intact.
+///
+/// The order they arrive in is reported rather than asserted. Groups travel on
+/// separate streams, and moq-lite sends the newest groups of a track first when
+/// several are queued, while `next_group` returns them in arrival order; so a
+/// burst like this one typically arrives as `6,7,0,1,2,3,4,5`. Only frames
+/// within a group are guaranteed to stay in order.
+async fn test_group_ordering(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+    anyhow::ensure!(
+        pub_track.info.name == ctx.track,
+        "publisher received request for unexpected track: {}",
+        pub_track.info.name
+    );
+
+    for _ in 0..GROUP_ORDERING_GROUPS {
+        let mut group = pub_track.append_group().context("failed to create group")?;
+        let payload = format!("group-{}", group.info.sequence);
+        group
+            .write_frame(payload.into_bytes())
+            .context("failed to write frame")?;
+        group.finish().context("failed to finish group")?;
+    }
+
+    let mut order = Vec::with_capacity(GROUP_ORDERING_GROUPS as usize);
+    let mut out_of_order = 0;
+    while (order.len() as u64) < GROUP_ORDERING_GROUPS {
+        let mut group = track
+            .next_group()
+            .await
+            .context("failed to read group")?
+            .with_context(|| {
+                format!(
+                    "track ended after {} of {} groups",
+                    order.len(),
+                    GROUP_ORDERING_GROUPS
+                )
+            })?;
+        let sequence = group.info.sequence;
+        anyhow::ensure!(
+            !order.contains(&sequence),
+            "group {} delivered twice",
+            sequence
+        );
+
+        let frame = group
+            .read_frame()
+            .await
+            .context("failed to read frame")?
+            .with_context(|| format!("group {} ended without a frame", sequence))?;
+        let expected = format!("group-{}", sequence);
+        anyhow::ensure!(
+            frame.as_ref() == expected.as_bytes(),
+            "group {} carried another group's payload: {:?}",
+            sequence,
+            String::from_utf8_lossy(&frame)
+        );
+
+        if order.last().is_some_and(|&last| sequence < last) {
+            out_of_order += 1;
+        }
+        order.push(sequence);
+    }
+
+    let group_order = order
+        .iter()
+        .map(|sequence| sequence.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let diagnostics = Diagnostics {
+        group_order: Some(group_order),
+        out_of_order: Some(out_of_order),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces a broadcast with no tracks, subscriber
+/// subscribes to the test track straight away, and only then does the publisher
+/// create the track and write a group to it.
+///
+/// Without a dynamic handler, moq-lite can't hold on to a request for a track
+/// it doesn't have, so the subscription may be rejected rather than left
+/// waiting. Either is allowed, as long as subscribing again once the track
+/// exists gets the data. Reports `early_subscription: buffered` if the first
+/// subscription got it, or `errored` if it had to be retried.
+async fn test_subscribe_before_track(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup, deliberately without `dynamic()`
+    let pub_origin = Origin::produce();
+    let mut broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let track = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+    let mut sub_track = sub_broadcast
+        .subscribe_track(&track)
+        .context("failed to subscribe to track")?;
+
+    // Give the subscription time to reach the publisher (and maybe be
+    // rejected) before the track exists.
+    let rejected = tokio::select! {
+        res = sub_track.next_group() => match res {
+            Ok(Some(group)) => anyhow::bail!(
+                "received group {} before the track was created",
+                group.info.sequence
+            ),
+            Ok(None) => true,
+            Err(err) => {
+                tracing::debug!(%err, "early subscription rejected");
+                true
+            }
+        },
+        _ = tokio::time::sleep(LATE_TRACK_DELAY) => false,
+    };
+
+    let mut pub_track = broadcast
+        .create_track(track.clone())
+        .context("failed to create track")?;
+    let mut pub_group = pub_track.append_group().context("failed to create group")?;
+    for frame in TEST_FRAMES {
+        pub_group
+            .write_frame(frame.to_vec())
+            .context("failed to write frame")?;
+    }
+    pub_group.finish().context("failed to finish group")?;
+
+    let early_subscription = match rejected {
+        true => {
+            sub_track = sub_broadcast
+                .subscribe_track(&track)
+                .context("failed to subscribe again once the track existed")?;
+            "errored"
+        }
+        false => "buffered",
+    };
+
+    let mut group = sub_track
+        .next_group()
+        .await
+        .with_context(|| format!("failed to read group ({} subscription)", early_subscription))?
+        .context("track ended before receiving a group")?;
+
+    for (index, expected) in TEST_FRAMES.iter().enumerate() {
+        let frame = group
+            .read_frame()
+            .await
+            .context("failed to read frame")?
+            .with_context(|| {
+                format!(
+                    "group ended after {} of {} frames",
+                    index,
+                    TEST_FRAMES.len()
+                )
+            })?;
+        anyhow::ensure!(
+            frame.as_ref() == *expected,
+            "frame {} mismatch: expected {:02x?}, got {:02x?}",
+            index,
+            expected,
+            frame.as_ref()
+        );
+    }
+
+    let diagnostics = Diagnostics {
+        early_subscription: Some(early_subscription),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: subscriber subscribes to the test track twice on the same
+/// broadcast, and both handles must receive the publisher's group.
+///
+/// Reports `duplicate_subscribe: deduplicated` if the second subscription
+/// shares the first one's track (moq-lite's behavior while the first is still
+/// open), or `independent` if it got a track of its own.
+async fn test_duplicate_subscribe(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let track = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+    let first = sub_broadcast
+        .subscribe_track(&track)
+        .context("failed to subscribe to track")?;
+    let second = sub_broadcast
+        .subscribe_track(&track)
+        .context("failed to subscribe to track a second time")?;
+    let duplicate_subscribe = match first.is_clone(&second) {
+        true => "deduplicated",
+        false => "independent",
+    };
+
+    // Even independent subscriptions share the publisher's track, so it's
+    // only ever asked for once.
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+    anyhow::ensure!(
+        pub_track.info.name == ctx.track,
+        "publisher received request for unexpected track: {}",
+        pub_track.info.name
+    );
+
+    let mut pub_group = pub_track.append_group().context("failed to create group")?;
+    for frame in TEST_FRAMES {
+        pub_group
+            .write_frame(frame.to_vec())
+            .context("failed to write frame")?;
+    }
+    pub_group.finish().context("failed to finish group")?;
+
+    for (label, mut sub_track) in [("first", first), ("second", second)] {
+        let mut group = sub_track
+            .next_group()
+            .await
+            .with_context(|| format!("{} subscription failed to read group", label))?
+            .with_context(|| format!("{} subscription ended before receiving a group", label))?;
+
+        let mut frames = Vec::new();
+        while let Some(frame) = group
+            .read_frame()
+            .await
+            .with_context(|| format!("{} subscription failed to read frame", label))?
+        {
+            frames.push(frame);
+        }
+        anyhow::ensure!(
+            frames
+                .iter()
+                .map(|f| &f[..])
+                .eq(TEST_FRAMES.iter().copied()),
+            "{} subscription received {} frames that don't match the {} published",
+            label,
+            frames.len(),
+            TEST_FRAMES.len()
+        );
+    }
+
+    let diagnostics = Diagnostics {
+        duplicate_subscribe: Some(duplicate_subscribe),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces a namespace full of control characters
+/// and far too long, and the relay must either carry on or close the session
+/// cleanly, and keep serving other sessions either way.
+///
+/// What counts as malformed is up to the relay, so any clean outcome passes; a
+/// hang, a reset, or a relay that stops answering fails. moq-lite has no way to
+/// refuse a single announcement, so a relay rejecting it can only drop it or
+/// close the session. Reports `malformed_namespace` as `forwarded` (a subscriber
+/// saw it), `dropped`, or `closed`, with `malformed_namespace_error` saying what
+/// closed the session.
+async fn test_malformed_namespace(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let malformed = format!(
+        "{}/{}{}",
+        ctx.namespace,
+        MALFORMED_PREFIX,
+        "x".repeat(MALFORMED_FILLER_BYTES)
+    );
+
+    let origin = Origin::produce();
+    let malformed_broadcast = Broadcast::produce();
+    origin.publish_broadcast(&malformed, malformed_broadcast.consume());
+
+    let mut pub_session = ctx
+        .client
+        .clone()
+        .with_publish(origin.consume())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("publisher failed to connect")?;
+
+    let closed = tokio::select! {
+        err = pub_session.closed() => Some(err),
+        _ = tokio::time::sleep(MALFORMED_WINDOW) => None,
+    };
+    if let Some(err) = &closed {
+        use moq_native::web_transport_quinn::quinn::ConnectionError;
+        anyhow::ensure!(
+            !matches!(err, ConnectionError::Reset | ConnectionError::TimedOut),
+            "relay dropped the session without closing it: {}",
+            err
+        );
+    }
+
+    // A session that survived should still be able to announce normally.
+    let broadcast = Broadcast::produce();
+    if closed.is_none() {
+        origin.publish_broadcast(ctx.namespace, broadcast.consume());
+    }
+
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let mut sub_session = ctx
+        .client
+        .clone()
+        .with_consume(sub_origin)
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("relay stopped accepting sessions after the malformed announce")?;
+
+    let outcome = match closed {
+        Some(_) => "closed",
+        None => {
+            // Announced first, so if the relay forwarded it, it has arrived by now.
+            wait_for_broadcast(&mut sub_consumer, ctx.namespace)
+                .await
+                .context("publisher session stopped working after the malformed announce")?;
+            match sub_consumer.consume_broadcast(&malformed) {
+                Some(_) => "forwarded",
+                None => "dropped",
+            }
+        }
+    };
+
+    let diagnostics = Diagnostics {
+        session_survived: Some(closed.is_none()),
+        malformed_namespace: Some(outcome),
+        malformed_namespace_error: closed.map(|err| err.to_string()),
+        ..Diagnostics::pub_sub(&pub_session, &sub_session)
+    };
+
+    pub_session.close(moq_lite::Error::Cancel);
+    sub_session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Answer every subscription on `broadcast` with one group at `sequence`, each
+/// frame naming `publisher`, until the broadcast closes.
+async fn serve_tagged(
+    broadcast: &mut BroadcastDynamic,
+    publisher: &'static str,
+    sequence: u64,
+) -> anyhow::Result<()> {
+    // Keep every track open, or the subscriber would see it end early.
+    let mut tracks = Vec::new();
+    loop {
+        let mut track = broadcast
+            .requested_track()
+            .await
+            .with_context(|| format!("{} publisher broadcast closed", publisher))?;
+        let mut group = track
+            .create_group(Group { sequence })
+            .context("failed to create group")?;
+        for _ in 0..CONFLICT_GROUP_FRAMES {
+            group
+                .write_frame(bytes::Bytes::from_static(publisher.as_bytes()))
+                .context("failed to write frame")?;
+        }
+        group.finish().context("failed to finish group")?;
+        tracks.push(track);
+    }
+}
+
+/// Three connections: two publishers announce the same namespace, one after the
+/// other, and a subscriber reads the track through the relay.
+///
+/// Each publisher answers with a group naming it, at a sequence of its own so
+/// the relay can't mistake one for the other. Any policy passes as long as
+/// data arrives and no group mixes the two publishers' frames. Reports
+/// `namespace_conflict` as `merged` (data from both), `last-writer-wins` or
+/// `first-writer-wins` (data from one), or `rejected` (the relay closed a
+/// publisher's session).
+async fn test_namespace_conflict(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // First publisher
+    let first_origin = Origin::produce();
+    let first = Broadcast::produce();
+    first_origin.publish_broadcast(ctx.namespace, first.consume());
+    let mut first_dynamic = first.dynamic();
+
+    let mut first_session = ctx
+        .client
+        .clone()
+        .with_publish(first_origin.consume())
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("first publisher failed to connect")?;
+
+    // Second publisher, same namespace
+    let second_origin = Origin::produce();
+    let second = Broadcast::produce();
+    second_origin.publish_broadcast(ctx.namespace, second.consume());
+    let mut second_dynamic = second.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &second_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let receive = async {
+        let mut publishers = Vec::new();
+        let mut deadline = None;
+        loop {
+            let next = async {
+                match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, track.next_group())
+                        .await
+                        .ok(),
+                    None => Some(track.next_group().await),
+                }
+            };
+            let Some(group) = next.await else {
+                return Ok(publishers);
+            };
+            let Some(mut group) = group.context("failed to read group")? else {
+                return Ok(publishers);
+            };
+
+            let mut frames = Vec::with_capacity(CONFLICT_GROUP_FRAMES);
+            while let Some(frame) = group.read_frame().await.context("failed to read frame")? {
+                frames.push(frame);
+            }
+            anyhow::ensure!(
+                frames.len() == CONFLICT_GROUP_FRAMES,
+                "group {} had {} frames, expected {}",
+                group.info.sequence,
+                frames.len(),
+                CONFLICT_GROUP_FRAMES
+            );
+            anyhow::ensure!(
+                frames.iter().all(|frame| *frame == frames[0]),
+                "group {} mixes frames from both publishers",
+                group.info.sequence
+            );
+            let publisher = match (group.info.sequence, &frames[0][..]) {
+                (0, b"first") => "first",
+                (1, b"second") => "second",
+                (sequence, frame) => anyhow::bail!(
+                    "group {} carried {:?}, which no publisher sent at that sequence",
+                    sequence,
+                    String::from_utf8_lossy(frame)
+                ),
+            };
+            if !publishers.contains(&publisher) {
+                publishers.push(publisher);
+            }
+
+            // Give the other publisher's group a moment to turn up too.
+            deadline.get_or_insert_with(|| tokio::time::Instant::now() + CONFLICT_WINDOW);
+        }
+    };
+
+    let publishers = tokio::select! {
+        res = serve_tagged(&mut first_dynamic, "first", 0) => {
+            res?;
+            anyhow::bail!("first publisher stopped serving")
+        }
+        res = serve_tagged(&mut second_dynamic, "second", 1) => {
+            res?;
+            anyhow::bail!("second publisher stopped serving")
+        }
+        publishers = receive => publishers?,
+    };
+    anyhow::ensure!(
+        !publishers.is_empty(),
+        "track ended before any data arrived"
+    );
+
+    let policy = match (
+        first_session.is_closed() || sessions.publisher.is_closed(),
+        &publishers[..],
+    ) {
+        (true, _) => "rejected",
+        (false, [_, _]) => "merged",
+        (false, ["second"]) => "last-writer-wins",
+        (false, _) => "first-writer-wins",
+    };
+
+    let diagnostics = Diagnostics {
+        second_publisher_connection_id: Some(sessions.publisher.id()),
+        namespace_conflict: Some(policy),
+        ..Diagnostics::pub_sub(&first_session, &sessions.subscriber)
+    };
+
+    first_session.close(moq_lite::Error::Cancel);
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: subscriber subscribes and reads a group, drops the
+/// subscription, then subscribes to the same track again and must receive a
+/// group published after that.
+///
+/// Reports `resubscribe: fresh` if the second subscription reached the
+/// publisher as a new one, or `retained` if the relay kept the first open
+/// upstream and served the second from it.
+async fn test_resubscribe(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+    let info = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+
+    // First subscription
+    let mut track = sub_broadcast
+        .subscribe_track(&info)
+        .context("failed to subscribe to track")?;
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+    pub_track
+        .write_frame(bytes::Bytes::from_static(b"first"))
+        .context("failed to write frame")?;
+
+    let mut group = track
+        .next_group()
+        .await
+        .context("failed to read group")?
+        .context("track ended before the first group arrived")?;
+    let frame = group
+        .read_frame()
+        .await
+        .context("failed to read frame")?
+        .context("first group was empty")?;
+    anyhow::ensure!(
+        frame == "first",
+        "first subscription received {:?}",
+        String::from_utf8_lossy(&frame)
+    );
+
+    // Unsubscribe, and give the relay a moment to notice
+    drop(group);
+    drop(track);
+    tokio::time::sleep(RESUBSCRIBE_GAP).await;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&info)
+        .context("failed to subscribe to track again")?;
+
+    // A relay that forwarded the unsubscribe has to subscribe upstream again.
+    let (state, mut pub_track) =
+        match tokio::time::timeout(RESUBSCRIBE_WINDOW, dynamic.requested_track()).await {
+            Ok(requested) => ("fresh", requested.context("publisher broadcast closed")?),
+            Err(_) => ("retained", pub_track),
+        };
+    pub_track
+        .write_frame(bytes::Bytes::from_static(b"second"))
+        .context("failed to write frame")?;
+
+    // A relay that kept state may replay the cached first group before the new one.
+    loop {
+        let mut group = track
+            .next_group()
+            .await
+            .context("failed to read group")?
+            .context("second subscription ended before any new data arrived")?;
+        let frame = group
+            .read_frame()
+            .await
+            .context("failed to read frame")?
+            .context("group was empty")?;
+        match &frame[..] {
+            b"second" => break,
+            b"first" => tracing::debug!("second subscription replayed the first group"),
+            frame => anyhow::bail!(
+                "second subscription received {:?}",
+                String::from_utf8_lossy(frame)
+            ),
+        }
+    }
+
+    let diagnostics = Diagnostics {
+        resubscribe: Some(state),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: subscriber subscribes to `--max-subscriptions` tracks one
+/// at a time, keeping each open, and the publisher answers each with a group.
+///
+/// Characterizes relay capacity rather than asserting one, so it passes as long
+/// as the first subscription works. Reports `subscriptions_succeeded`, and
+/// `subscription_limit` as `none` if every subscription delivered, `rejected`
+/// if the relay ended one with an error, or `stalled` if one's data didn't
+/// arrive within a second.
+async fn test_max_subscriptions(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    // Create each track as it's asked for, with a group naming it.
+    let serve = async {
+        let mut tracks = Vec::new();
+        loop {
+            let mut track = dynamic
+                .requested_track()
+                .await
+                .context("publisher broadcast closed")?;
+            track
+                .write_frame(bytes::Bytes::from(track.info.name.clone()))
+                .context("failed to write frame")?;
+            tracks.push(track);
+        }
+    };
+
+    let subscribe = async {
+        // Every subscription stays open, so the relay has to carry them all at once.
+        let mut tracks = Vec::with_capacity(ctx.max_subscriptions as usize);
+        for index in 0..ctx.max_subscriptions {
+            let name = format!("{}-{}", ctx.track, index);
+            let mut track = match sub_broadcast.subscribe_track(&Track {
+                name: name.clone(),
+                priority: 0,
+            }) {
+                Ok(track) => track,
+                Err(err) => {
+                    tracing::info!(index, %err, "subscription rejected");
+                    return (index, "rejected");
+                }
+            };
+
+            let received = async {
+                let mut group = track.next_group().await?.context("track ended")?;
+                let frame = group.read_frame().await?.context("group was empty")?;
+                anyhow::ensure!(
+                    frame == name.as_bytes(),
+                    "track {} received {:?}",
+                    name,
+                    String::from_utf8_lossy(&frame)
+                );
+                Ok(())
+            };
+            match tokio::time::timeout(SUBSCRIPTION_STALL, received).await {
+                Ok(Ok(())) => tracks.push(track),
+                Ok(Err(err)) => {
+                    tracing::info!(index, "subscription rejected: {:#}", err);
+                    return (index, "rejected");
+                }
+                Err(_) => {
+                    tracing::info!(index, "subscription stalled");
+                    return (index, "stalled");
+                }
+            }
+        }
+        (ctx.max_subscriptions, "none")
+    };
+
+    let (succeeded, limit) = tokio::select! {
+        result = serve => return result,
+        outcome = subscribe => outcome,
+    };
+    anyhow::ensure!(
+        succeeded > 0,
+        "the first subscription was {} by the relay",
+        limit
+    );
+
+    let diagnostics = Diagnostics {
+        subscriptions_succeeded: Some(succeeded),
+        subscription_limit: Some(limit),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Like `setup-only`, against a relay given by IPv6 address, checking the
+/// connection really runs over IPv6 rather than a mapped IPv4 address.
+async fn test_setup_only_ipv6(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let mut session = ctx
+        .client
+        .clone()
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect")?;
+
+    let remote = session.remote_address();
+    anyhow::ensure!(
+        matches!(remote.ip(), std::net::IpAddr::V6(ip) if ip.to_ipv4_mapped().is_none()),
+        "connected over IPv4 ({}) instead of IPv6",
+        remote
+    );
+
+    let diagnostics = Diagnostics::single(&session);
+    session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes a group to the test track every
+/// `LIVE_GROUP_INTERVAL` from before either side connects, and the subscriber
+/// times how long after subscribing its first object arrives.
+async fn test_time_to_first_object(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let info = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+
+    // Publisher setup, live before anyone subscribes
+    let pub_origin = Origin::produce();
+    let mut broadcast = Broadcast::produce();
+    let mut pub_track = broadcast
+        .create_track(info.clone())
+        .context("failed to create track")?;
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let subscribe = async {
+        let sessions = ctx
+            .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+            .await?;
+        let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+        let start = Instant::now();
+        let mut track = sub_broadcast
+            .subscribe_track(&info)
+            .context("failed to subscribe to track")?;
+        let mut group = track
+            .next_group()
+            .await
+            .context("failed to read group")?
+            .context("track ended before any object arrived")?;
+        group
+            .read_frame()
+            .await
+            .context("failed to read frame")?
+            .context("first group was empty")?;
+        let ttfo = start.elapsed();
+
+        anyhow::Ok((sessions, ttfo))
+    };
+
+    let (sessions, ttfo) = tokio::select! {
+        res = subscribe => res?,
+        res = produce_live(&mut pub_track, LIVE_GROUP_INTERVAL) => {
+            res?;
+            anyhow::bail!("publisher stopped producing")
+        }
+    };
+
+    let diagnostics = Diagnostics {
+        ttfo_ms: Some(millis(ttfo)),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Append a one-frame group to `track` every `interval` until writing fails.
+async fn produce_live(track: &mut TrackProducer, interval: Duration) -> anyhow::Result<()> {
+    for sequence in 0u64.. {
+        let mut group = track.append_group().context("failed to create group")?;
+        group
+            .write_frame(bytes::Bytes::from(sequence.to_string()))
+            .context("failed to write frame")?;
+        group.finish().context("failed to finish group")?;
+        tokio::time::sleep(interval).await;
+    }
+    Ok(())
+}
+
+/// Like `produce_live`, but each frame is `sequence:micros`, stamped with the
+/// microseconds since `epoch` it was written at.
+async fn produce_timestamped(
+    track: &mut TrackProducer,
+    interval: Duration,
+    epoch: Instant,
+) -> anyhow::Result<()> {
+    for sequence in 0u64.. {
+        let mut group = track.append_group().context("failed to create group")?;
+        let payload = format!("{}:{}", sequence, epoch.elapsed().as_micros());
+        group
+            .write_frame(payload.into_bytes())
+            .context("failed to write frame")?;
+        group.finish().context("failed to finish group")?;
+        tokio::time::sleep(interval).await;
+    }
+    Ok(())
+}
+
+/// Two connections in turn: the first leaves a TLS session ticket behind, and
+/// the second resumes with it, attempting 0-RTT.
+///
+/// Reports `early_data: accepted` or `rejected` for the relay's answer, or
+/// `unavailable` if it issued no ticket allowing early data, along with how
+/// much quicker the second connection was set up.
+async fn test_zero_rtt_resume(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let client = ctx.client.clone().with_zero_rtt();
+
+    let mut first = client
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect")?;
+    first.close(moq_lite::Error::Cancel);
+
+    let mut second = client
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to reconnect")?;
+
+    let early_data = match second.early_data() {
+        Some(true) => "accepted",
+        Some(false) => "rejected",
+        None => "unavailable",
+    };
+    let saved = millis(first.connect_time()) - millis(second.connect_time());
+
+    let diagnostics = Diagnostics {
+        early_data: Some(early_data),
+        handshake_saved_ms: Some((saved * 1000.0).round() / 1000.0),
+        ..Diagnostics::single(&second)
+    };
+
+    second.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Connect and open a control stream of an undefined type, giving the relay
+/// cause to close the session, then check that the close reaches us with a
+/// code rather than as a reset or a hang.
+///
+/// A relay that ignores the stream passes too, with `relay_close: none`, as
+/// long as the session stays up for the whole window.
+async fn test_relay_initiated_close(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    use moq_native::web_transport_quinn::quinn::ConnectionError;
+
+    let mut session = ctx
+        .client
+        .clone()
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect")?;
+
+    session.open_invalid_stream().await?;
+
+    let closed = tokio::select! {
+        err = session.closed() => Some(err),
+        _ = tokio::time::sleep(RELAY_CLOSE_WINDOW) => None,
+    };
+
+    let diagnostics = match closed {
+        Some(err) => match session.peer_close() {
+            Some((code, reason)) => Diagnostics {
+                relay_close: Some("closed"),
+                close_code: Some(code),
+                close_reason: Some(reason),
+                ..Diagnostics::single(&session)
+            },
+            // Over WebTransport our side closes the connection itself on reading the
+            // relay's CLOSE_WEBTRANSPORT_SESSION, and doesn't keep its code.
+            None if session.is_webtransport() && err == ConnectionError::LocallyClosed => {
+                Diagnostics {
+                    relay_close: Some("closed"),
+                    ..Diagnostics::single(&session)
+                }
+            }
+            None => anyhow::bail!("session ended without a close from the relay: {}", err),
+        },
+        None => Diagnostics {
+            relay_close: Some("none"),
+            ..Diagnostics::single(&session)
+        },
+    };
+
+    session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces `BACKLOG_BROADCASTS` broadcasts, and a
+/// subscriber connecting only afterwards must discover every one, in any order.
+async fn test_backlog_announce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let paths: Vec<String> = (0..BACKLOG_BROADCASTS)
+        .map(|i| format!("{}/backlog-{}", ctx.namespace, i))
+        .collect();
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcasts: Vec<_> = paths.iter().map(|_| Broadcast::produce()).collect();
+    for (path, broadcast) in paths.iter().zip(&broadcasts) {
+        pub_origin.publish_broadcast(path, broadcast.consume());
+    }
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, BACKLOG_DELAY)
+        .await?;
+
+    wait_for_broadcasts(&mut sub_consumer, &paths, BACKLOG_WINDOW).await?;
+
+    let diagnostics = Diagnostics {
+        announced_count: Some(paths.len()),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Three connections: publisher writes a live track, one subscriber subscribes
+/// to it and closes its session straight away without reading anything, and
+/// a second subscriber must still be able to receive from it afterwards.
+async fn test_subscribe_abort(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let info = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let mut broadcast = Broadcast::produce();
+    let mut pub_track = broadcast
+        .create_track(info.clone())
+        .context("failed to create track")?;
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // First subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let subscribe = async {
+        let SessionPair {
+            publisher,
+            mut subscriber,
+        } = ctx
+            .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+            .await?;
+
+        // Subscribe and abandon the session at once
+        let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+        let track = sub_broadcast
+            .subscribe_track(&info)
+            .context("failed to subscribe to track")?;
+        subscriber.close(moq_lite::Error::Cancel);
+        drop(track);
+
+        tokio::time::sleep(SUBSCRIBE_ABORT_GAP).await;
+
+        // Second subscriber
+        let second_origin = Origin::produce();
+        let mut second_consumer = second_origin.consume();
+        let second_session = ctx
+            .client
+            .clone()
+            .with_consume(second_origin)
+            .connect(ctx.relay_url.clone())
+            .await
+            .context("second subscriber failed to connect")?;
+
+        let second_broadcast = wait_for_broadcast(&mut second_consumer, ctx.namespace)
+            .await
+            .context("second subscriber never discovered the broadcast")?;
+        let mut track = second_broadcast
+            .subscribe_track(&info)
+            .context("second subscriber failed to subscribe to track")?;
+        let mut group = track
+            .next_group()
+            .await
+            .context("second subscriber failed to read group")?
+            .context("track ended before the second subscriber received a group")?;
+        group
+            .read_frame()
+            .await
+            .context("second subscriber failed to read frame")?
+            .context("group was empty")?;
+
+        anyhow::Ok((publisher, subscriber, second_session))
+    };
+
+    let (mut publisher, subscriber, mut second_session) = tokio::select! {
+        res = subscribe => res?,
+        res = produce_live(&mut pub_track, LIVE_GROUP_INTERVAL) => {
+            res?;
+            anyhow::bail!("publisher stopped producing")
+        }
+    };
+
+    let diagnostics = Diagnostics {
+        second_subscriber_connection_id: Some(second_session.id()),
+        second_subscriber_received: Some(true),
+        ..Diagnostics::pub_sub(&publisher, &subscriber)
+    };
+
+    publisher.close(moq_lite::Error::Cancel);
+    second_session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections presenting `--auth-token`: publisher announces a broadcast
+/// and the subscriber must discover it.
+///
+/// Only a setup close the relay marks as an auth failure counts as
+/// `unauthorized`; a missing announcement is left to the test timeout.
+async fn test_authenticated_announce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let rejected = match ctx.client.auth_token() {
+        Some(_) => "relay rejected --auth-token",
+        None => "relay requires --auth-token",
+    };
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await
+        .map_err(|err| match err.downcast_ref::<connect::PeerClosed>() {
+            Some(close) if is_auth_close(close) => err.context(AuthRejected(rejected)),
+            _ => err,
+        })?;
+
+    wait_for_broadcast(&mut sub_consumer, ctx.namespace)
+        .await
+        .context("subscriber never discovered the broadcast")?;
+
+    let diagnostics = Diagnostics {
+        authenticated: Some(sessions.publisher.authenticated()),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces and withdraws the test namespace
+/// `--churn-cycles` times in quick succession, while the subscriber counts the
+/// announcements and withdrawals that reach it.
+///
+/// The relay may coalesce flaps, so the counts needn't reach the number of
+/// cycles, but they must pair up, leave the namespace withdrawn once the churn
+/// settles, and not cost either session.
+async fn test_announce_churn(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    for _ in 0..ctx.churn_cycles {
+        let broadcast = Broadcast::produce();
+        pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+        tokio::time::sleep(CHURN_INTERVAL).await;
+        drop(broadcast);
+        tokio::time::sleep(CHURN_INTERVAL).await;
+    }
+
+    // Count what arrived, until the relay has been quiet for a while
+    let mut announces = 0u32;
+    let mut withdrawals = 0u32;
+    let mut announced = false;
+    while let Ok(update) = tokio::time::timeout(CHURN_QUIET, sub_consumer.announced()).await {
+        match update.context("consumer closed")? {
+            (path, Some(_)) if path.as_str() == ctx.namespace => {
+                announces += 1;
+                announced = true;
+            }
+            (path, None) if path.as_str() == ctx.namespace => {
+                withdrawals += 1;
+                announced = false;
+            }
+            (path, _) => tracing::debug!(%path, "ignoring unrelated announcement"),
+        }
+    }
+
+    anyhow::ensure!(
+        !sessions.publisher.is_closed() && !sessions.subscriber.is_closed(),
+        "relay closed a session during the churn"
+    );
+    anyhow::ensure!(
+        announces > 0,
+        "subscriber never saw the namespace announced"
+    );
+    anyhow::ensure!(
+        !announced,
+        "namespace still announced after {} cycles settled",
+        ctx.churn_cycles
+    );
+    anyhow::ensure!(
+        announces == withdrawals,
+        "saw {} announcements but {} withdrawals",
+        announces,
+        withdrawals
+    );
+
+    let diagnostics = Diagnostics {
+        announce_transitions: Some(announces + withdrawals),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes `IMPAIRED_OBJECTS` one-object groups at
+/// a steady pace, and the subscriber counts how many of them arrive.
+///
+/// Meant for running under `--simulate-loss` and `--simulate-jitter-ms`, to
+/// see how much a relay still delivers; it passes as long as anything does.
+async fn test_impaired_delivery(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    let publish = async {
+        for _ in 0..IMPAIRED_OBJECTS {
+            let mut group = pub_track.append_group().context("failed to create group")?;
+            let payload = format!("object-{}", group.info.sequence);
+            group
+                .write_frame(payload.into_bytes())
+                .context("failed to write frame")?;
+            group.finish().context("failed to finish group")?;
+            tokio::time::sleep(LIVE_GROUP_INTERVAL).await;
+        }
+        anyhow::Ok(())
+    };
+
+    // Count distinct objects until the last one arrives, or nothing has for a while
+    let receive = async {
+        let mut delivered = std::collections::HashSet::new();
+        while (delivered.len() as u64) < IMPAIRED_OBJECTS {
+            let next = async {
+                let mut group = match track.next_group().await.context("failed to read group")? {
+                    Some(group) => group,
+                    None => return anyhow::Ok(None),
+                };
+                let frame = group.read_frame().await.context("failed to read frame")?;
+                Ok(frame.map(|_| group.info.sequence))
+            };
+            match tokio::time::timeout(IMPAIRED_WINDOW, next).await {
+                Ok(Ok(Some(sequence))) => {
+                    delivered.insert(sequence);
+                }
+                Ok(Ok(None)) | Err(_) => break,
+                Ok(Err(err)) => return Err(err),
+            }
+        }
+        anyhow::Ok(delivered.len() as u64)
+    };
+
+    let ((), delivered) = tokio::try_join!(publish, receive)?;
+    anyhow::ensure!(
+        delivered > 0,
+        "none of {} objects delivered",
+        IMPAIRED_OBJECTS
+    );
+
+    let diagnostics = Diagnostics {
+        objects_expected: Some(IMPAIRED_OBJECTS),
+        objects_delivered: Some(delivered),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes one group of `BOUNDARY_SIZES` objects,
+/// each led by its own body length, and the subscriber checks every object
+/// arrives whole and on its own.
+///
+/// A relay that concatenates objects, or splits one across several, can still
+/// pass a test that compares the bytes end to end; the length prefixes show
+/// where the boundaries moved. Every violation is collected before failing.
+async fn test_object_boundary(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let objects: Vec<Vec<u8>> = BOUNDARY_SIZES
+        .iter()
+        .enumerate()
+        .map(|(index, &size)| {
+            let mut object = (size as u32).to_be_bytes().to_vec();
+            object.extend((0..size).map(|i| (index * 31 + i) as u8));
+            object
+        })
+        .collect();
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let mut track = sub_broadcast
+        .subscribe_track(&Track {
+            name: ctx.track.to_string(),
+            priority: 0,
+        })
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    let mut pub_group = pub_track.append_group().context("failed to create group")?;
+    for object in &objects {
+        pub_group
+            .write_frame(object.clone())
+            .context("failed to write frame")?;
+    }
+    pub_group.finish().context("failed to finish group")?;
+
+    let mut group = track
+        .next_group()
+        .await
+        .context("failed to read group")?
+        .context("track ended before receiving a group")?;
+
+    let mut violations = Vec::new();
+    let mut delivered = 0;
+    while let Some(frame) = group.read_frame().await.context("failed to read frame")? {
+        let index = delivered;
+        delivered += 1;
+
+        if frame.len() < BOUNDARY_PREFIX_LEN {
+            violations.push(format!(
+                "object {} split: {} bytes, too short for its length",
+                index,
+                frame.len()
+            ));
+            continue;
+        }
+        let (prefix, body) = frame.split_at(BOUNDARY_PREFIX_LEN);
+        let declared = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+        match body.len().cmp(&declared) {
+            std::cmp::Ordering::Greater => violations.push(format!(
+                "object {} coalesced: declared {} bytes, got {}",
+                index,
+                declared,
+                body.len()
+            )),
+            std::cmp::Ordering::Less => violations.push(format!(
+                "object {} split: declared {} bytes, got {}",
+                index,
+                declared,
+                body.len()
+            )),
+            std::cmp::Ordering::Equal => {
+                if objects.get(index).map(Vec::as_slice) != Some(&frame[..]) {
+                    violations.push(format!("object {} altered", index));
+                }
+            }
+        }
+    }
+    if delivered != objects.len() {
+        violations.push(format!(
+            "sent {} objects, received {}",
+            objects.len(),
+            delivered
+        ));
+    }
+    anyhow::ensure!(violations.is_empty(), "{}", violations.join("; "));
+
+    let diagnostics = Diagnostics {
+        objects_expected: Some(objects.len() as u64),
+        objects_delivered: Some(delivered as u64),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes to two tracks of one broadcast at once,
+/// alternating object by object, with every payload naming its track, group
+/// and object; the subscriber checks each object arrived on the track it
+/// names, in order within its group.
+async fn test_interleaved_tracks(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let tracks = [multi_track(0), multi_track(1)];
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let subscribe = |track: &Track| {
+        sub_broadcast
+            .subscribe_track(track)
+            .with_context(|| format!("failed to subscribe to track: {}", track.name))
+    };
+    let mut first = subscribe(&tracks[0])?;
+    let mut second = subscribe(&tracks[1])?;
+
+    // Wait for both requests, so neither track gets a head start
+    let mut pub_tracks = requested_tracks(&mut dynamic, tracks.each_ref()).await?;
+
+    for _ in 0..INTERLEAVED_GROUPS {
+        let mut groups = Vec::with_capacity(pub_tracks.len());
+        for pub_track in &mut pub_tracks {
+            let group = pub_track.append_group().context("failed to create group")?;
+            groups.push((pub_track.info.name.clone(), group));
+        }
+        for object in 0..INTERLEAVED_OBJECTS {
+            for (name, group) in &mut groups {
+                let payload = format!("{}:{}:{}", name, group.info.sequence, object);
+                group
+                    .write_frame(payload.into_bytes())
+                    .context("failed to write frame")?;
+            }
+        }
+        for (_, mut group) in groups {
+            group.finish().context("failed to finish group")?;
+        }
+    }
+
+    // Read both tracks at once, as their groups arrive
+    let (first, second) = tokio::try_join!(
+        read_interleaved(&tracks[0], &mut first),
+        read_interleaved(&tracks[1], &mut second)
+    )?;
+
+    let diagnostics = Diagnostics {
+        track_objects: Some(format!(
+            "{}={},{}={}",
+            tracks[0].name, first, tracks[1].name, second
+        )),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Read `interleaved-tracks`' groups from one of its tracks, checking each
+/// object names this track, and return how many objects arrived.
+async fn read_interleaved(track: &Track, sub_track: &mut TrackConsumer) -> anyhow::Result<u64> {
+    let mut received = 0;
+    for _ in 0..INTERLEAVED_GROUPS {
+        let mut group = sub_track
+            .next_group()
+            .await
+            .with_context(|| format!("failed to read group on track: {}", track.name))?
+            .with_context(|| format!("track {} ended after {} objects", track.name, received))?;
+        for object in 0..INTERLEAVED_OBJECTS {
+            let frame = group
+                .read_frame()
+                .await
+                .with_context(|| format!("failed to read frame on track: {}", track.name))?
+                .with_context(|| {
+                    format!(
+                        "group {} on track {} ended after {} objects",
+                        group.info.sequence, track.name, object
+                    )
+                })?;
+            let expected = format!("{}:{}:{}", track.name, group.info.sequence, object);
+            anyhow::ensure!(
+                frame.as_ref() == expected.as_bytes(),
+                "track {} received {:?}, expected {:?}",
+                track.name,
+                String::from_utf8_lossy(&frame),
+                expected
+            );
+            received += 1;
+        }
+    }
+    Ok(received)
+}
+
+/// Two connections: publisher announces `--announce-count` broadcasts on its
+/// one session, and the subscriber must discover every one of them.
+///
+/// Reports how long discovery took after the subscriber connected, to show
+/// how announcing scales with the number of namespaces.
+async fn test_bulk_announce(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let paths: Vec<String> = (0..ctx.announce_count)
+        .map(|i| format!("{}/bulk-{}", ctx.namespace, i))
+        .collect();
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcasts: Vec<_> = paths.iter().map(|_| Broadcast::produce()).collect();
+    for (path, broadcast) in paths.iter().zip(&broadcasts) {
+        pub_origin.publish_broadcast(path, broadcast.consume());
+    }
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let start = Instant::now();
+    wait_for_broadcasts(&mut sub_consumer, &paths, BULK_ANNOUNCE_WINDOW).await?;
+    let discovery = start.elapsed();
+
+    anyhow::ensure!(
+        !sessions.publisher.is_closed() && !sessions.subscriber.is_closed(),
+        "relay closed a session after the announcements"
+    );
+
+    let diagnostics = Diagnostics {
+        announced_count: Some(paths.len()),
+        discovery_ms: Some(millis(discovery)),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Like `setup-only`, but always verifying the relay's certificate, against
+/// `--ca-cert` or the system roots, even when the other tests don't.
+async fn test_tls_verify(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let client = ctx
+        .verifying_client
+        .context("no verifying client without --tls-verify-test")?;
+    let mut session = client
+        .connect(ctx.relay_url.clone())
+        .await
+        .context("failed to connect with certificate verification")?;
+
+    let diagnostics = Diagnostics::single(&session);
+    session.close(moq_lite::Error::Cancel);
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher floods the same groups onto a priority-0 and a
+/// top-priority track, alternating between them and starting each pair with the
+/// priority-0 track, and the top-priority groups should arrive sooner.
+///
+/// Reports the difference in average latency, from the start of the flood until
+/// each group finished arriving. Without congestion both tracks arrive about as
+/// fast, so the test only fails if the top-priority track is clearly slower.
+async fn test_priority_delivery(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let low = Track {
+        name: format!("{}-p0", ctx.track),
+        priority: 0,
+    };
+    let high = Track {
+        name: format!("{}-p{}", ctx.track, u8::MAX),
+        priority: u8::MAX,
+    };
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+    let subscribe = |track: &Track| {
+        sub_broadcast
+            .subscribe_track(track)
+            .with_context(|| format!("failed to subscribe to track: {}", track.name))
+    };
+    let sub_low = subscribe(&low)?;
+    let sub_high = subscribe(&high)?;
+
+    // Wait for both requests, so neither track gets a head start
+    let [mut pub_low, mut pub_high] = requested_tracks(&mut dynamic, [&low, &high]).await?;
+
+    let start = Instant::now();
+    let frame = bytes::Bytes::from(vec![0u8; PRIORITY_FRAME_SIZE]);
+    for _ in 0..PRIORITY_GROUPS {
+        for pub_track in [&mut pub_low, &mut pub_high] {
+            let mut group = pub_track.append_group().context("failed to create group")?;
+            for _ in 0..PRIORITY_FRAMES {
+                group
+                    .write_frame(frame.clone())
+                    .context("failed to write frame")?;
+            }
+            group.finish().context("failed to finish group")?;
+        }
+    }
+
+    // Read every group concurrently, so each latency is when it actually
+    // finished arriving rather than when the one before it was read.
+    let receive = |mut track: TrackConsumer, name: String| async move {
+        let mut reading = tokio::task::JoinSet::new();
+        for _ in 0..PRIORITY_GROUPS {
+            let mut group = track
+                .next_group()
+                .await
+                .with_context(|| format!("failed to read group on track: {}", name))?
+                .with_context(|| format!("track {} ended early", name))?;
+            reading.spawn(async move {
+                while group.read_frame().await?.is_some() {}
+                anyhow::Ok(start.elapsed())
+            });
+        }
+
+        // Hold on to the track until every group is read; dropping it unsubscribes.
+        let mut total = Duration::ZERO;
+        while let Some(result) = reading.join_next().await {
+            total += result
+                .context("reader task panicked")?
+                .with_context(|| format!("failed to read frame on track: {}", name))?;
+        }
+        anyhow::Ok(total / PRIORITY_GROUPS as u32)
+    };
+    let (low_latency, high_latency) = tokio::try_join!(
+        receive(sub_low, low.name.clone()),
+        receive(sub_high, high.name.clone())
+    )?;
+
+    let diagnostics = Diagnostics {
+        latency_delta_ms: Some({
+            let delta = low_latency.as_secs_f64() - high_latency.as_secs_f64();
+            (delta * 1_000_000.0).round() / 1000.0
+        }),
+        bytes_transferred: Some(
+            (2 * PRIORITY_GROUPS * PRIORITY_FRAMES * PRIORITY_FRAME_SIZE) as u64,
+        ),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    anyhow::ensure!(
+        high_latency.as_secs_f64()
+            <= low_latency.as_secs_f64() * (1.0 + PRIORITY_DELIVERY_TOLERANCE),
+        "priority {} groups arrived in {}ms on average, slower than priority 0's {}ms",
+        u8::MAX,
+        millis(high_latency),
+        millis(low_latency)
+    );
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes a timestamped group every
+/// `LIVE_GROUP_INTERVAL` for as long as the test runs, and the subscriber
+/// follows the stream for `LIVE_STREAM_DURATION`, checking no group is skipped
+/// and their timestamps run in order.
+///
+/// Reports how many objects arrived, how many gaps there were, and the longest
+/// wait between two groups, the stall a viewer would have seen.
+async fn test_live_stream(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let info = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+
+    // Publisher setup, live before anyone subscribes
+    let pub_origin = Origin::produce();
+    let mut broadcast = Broadcast::produce();
+    let mut pub_track = broadcast
+        .create_track(info.clone())
+        .context("failed to create track")?;
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let epoch = Instant::now();
+    let subscribe = async {
+        let sessions = ctx
+            .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+            .await?;
+        let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+
+        let mut track = sub_broadcast
+            .subscribe_track(&info)
+            .context("failed to subscribe to track")?;
+
+        // Each group has a stream of its own, so under loss a later one can
+        // overtake an earlier one; only those that never arrive are gaps.
+        let deadline = tokio::time::Instant::now() + LIVE_STREAM_DURATION;
+        let mut stamps = std::collections::BTreeMap::new();
+        let mut max_gap = Duration::ZERO;
+        let mut last_arrived = None;
+        loop {
+            let mut group = match tokio::time::timeout_at(deadline, track.next_group()).await {
+                Ok(group) => group
+                    .context("failed to read group")?
+                    .with_context(|| format!("track ended after {} objects", stamps.len()))?,
+                Err(_) => break,
+            };
+            let frame = group
+                .read_frame()
+                .await
+                .context("failed to read frame")?
+                .with_context(|| format!("group {} was empty", group.info.sequence))?;
+            let arrived = Instant::now();
+
+            let payload = String::from_utf8_lossy(&frame);
+            let (sequence, stamp): (u64, u128) = payload
+                .split_once(':')
+                .and_then(|(sequence, stamp)| Some((sequence.parse().ok()?, stamp.parse().ok()?)))
+                .with_context(|| format!("malformed object: {:?}", payload))?;
+            anyhow::ensure!(
+                sequence == group.info.sequence,
+                "group {} carried the object for group {}",
+                group.info.sequence,
+                sequence
+            );
+            anyhow::ensure!(
+                stamps.insert(sequence, stamp).is_none(),
+                "group {} arrived twice",
+                sequence
+            );
+
+            if let Some(last_arrived) = last_arrived {
+                max_gap = max_gap.max(arrived - last_arrived);
+            }
+            last_arrived = Some(arrived);
+        }
+
+        // The stream is joined wherever it is, so it only has to be continuous
+        // from the first group received.
+        let (mut gaps, mut missing) = (0, 0);
+        let mut previous: Option<(u64, u128)> = None;
+        for (&sequence, &stamp) in &stamps {
+            if let Some((previous_sequence, previous_stamp)) = previous {
+                anyhow::ensure!(
+                    stamp >= previous_stamp,
+                    "group {} is timestamped before group {}",
+                    sequence,
+                    previous_sequence
+                );
+                if sequence > previous_sequence + 1 {
+                    gaps += 1;
+                    missing += sequence - previous_sequence - 1;
+                }
+            }
+            previous = Some((sequence, stamp));
+        }
+
+        anyhow::Ok((sessions, stamps.len() as u64, gaps, missing, max_gap))
+    };
+
+    let (sessions, received, gaps, missing, max_gap) = tokio::select! {
+        res = subscribe => res?,
+        res = produce_timestamped(&mut pub_track, LIVE_GROUP_INTERVAL, epoch) => {
+            res?;
+            anyhow::bail!("publisher stopped producing")
+        }
+    };
+
+    let diagnostics = Diagnostics {
+        objects_delivered: Some(received),
+        gaps_detected: Some(gaps),
+        max_interarrival_ms: Some(millis(max_gap)),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    anyhow::ensure!(
+        received > 0,
+        "no objects arrived within {}ms",
+        LIVE_STREAM_DURATION.as_millis()
+    );
+    anyhow::ensure!(
+        gaps == 0,
+        "{} groups were skipped across {} gaps, out of {} received; longest wait {}ms",
+        missing,
+        gaps,
+        received,
+        millis(max_gap)
+    );
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher announces a namespace, and serves a track, whose
+/// names are mostly non-ASCII, and the subscriber must discover and subscribe
+/// to them with every byte intact.
+///
+/// moq-lite names are strings, so the names here are UTF-8; bytes that aren't
+/// valid UTF-8 can't be sent at all. That still catches relays that mangle or
+/// reject anything outside ASCII. Reports `names_preserved` and the namespace
+/// as announced.
+async fn test_binary_names(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let prefix = format!("{}/binary-names/", ctx.namespace);
+    let namespace = format!("{}{}", prefix, BINARY_NAMESPACE);
+    let track = Track {
+        name: format!("{}-{}", ctx.track, BINARY_TRACK),
+        priority: 0,
+    };
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(&namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    // Take whatever turns up under the ASCII prefix, so a relay that altered
+    // the rest is caught rather than left waiting for the original.
+    let discover = async {
+        loop {
+            match sub_consumer.announced().await.context("consumer closed")? {
+                (path, Some(broadcast)) if path.as_str().starts_with(&prefix) => {
+                    return anyhow::Ok((path.as_str().to_string(), broadcast));
+                }
+                (path, _) => tracing::debug!(%path, "ignoring unrelated announcement"),
+            }
+        }
+    };
+    let (announced, sub_broadcast) = tokio::time::timeout(BINARY_NAMES_WINDOW, discover)
+        .await
+        .with_context(|| {
+        format!(
+            "no announcement under {} within {}ms",
+            prefix,
+            BINARY_NAMES_WINDOW.as_millis()
+        )
+    })??;
+    anyhow::ensure!(
+        announced == namespace,
+        "relay altered the namespace: announced {:?} ({} bytes), expected {:?} ({} bytes)",
+        announced,
+        announced.len(),
+        namespace,
+        namespace.len()
+    );
+
+    let mut sub_track = sub_broadcast
+        .subscribe_track(&track)
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+    anyhow::ensure!(
+        pub_track.info.name == track.name,
+        "relay altered the track name: requested {:?} ({} bytes), expected {:?} ({} bytes)",
+        pub_track.info.name,
+        pub_track.info.name.len(),
+        track.name,
+        track.name.len()
+    );
+
+    // The names as the payload, so the data path is checked too.
+    let payload = format!("{}\n{}", namespace, track.name);
+    let mut group = pub_track.append_group().context("failed to create group")?;
+    group
+        .write_frame(payload.clone().into_bytes())
+        .context("failed to write frame")?;
+    group.finish().context("failed to finish group")?;
+
+    let mut sub_group = sub_track
+        .next_group()
+        .await
+        .context("failed to read group")?
+        .context("track ended before any object arrived")?;
+    let frame = sub_group
+        .read_frame()
+        .await
+        .context("failed to read frame")?
+        .context("group was empty")?;
+    anyhow::ensure!(
+        frame.as_ref() == payload.as_bytes(),
+        "object altered in transit: received {:?}",
+        String::from_utf8_lossy(&frame)
+    );
+
+    let diagnostics = Diagnostics {
+        announced_path: Some(announced),
+        names_preserved: Some(true),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// Two connections: publisher writes `BACKPRESSURE_GROUPS` large groups as fast
+/// as it can, and the subscriber reads them one at a time in order, pausing
+/// after each; every object must still arrive, in full and unaltered.
+///
+/// moq-lite's writes never block: groups are buffered in memory until the
+/// session sends them, and read off the wire into memory on the other side
+/// whether or not the application is keeping up. So rather than the publisher
+/// stalling, this observes where the data waited, sampling after each read how
+/// much the subscriber had received but not read, and how much the publisher
+/// had written but not sent. Both count QUIC overhead against the payload, so
+/// they are estimates.
+async fn test_backpressure(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let info = Track {
+        name: ctx.track.to_string(),
+        priority: 0,
+    };
+
+    // Publisher setup
+    let pub_origin = Origin::produce();
+    let broadcast = Broadcast::produce();
+    pub_origin.publish_broadcast(ctx.namespace, broadcast.consume());
+
+    let mut dynamic = broadcast.dynamic();
+
+    // Subscriber setup
+    let sub_origin = Origin::produce();
+    let mut sub_consumer = sub_origin.consume();
+
+    let sessions = ctx
+        .session_pair(ctx.relay_url, &pub_origin, sub_origin, Duration::ZERO)
+        .await?;
+
+    let sub_broadcast = wait_for_broadcast(&mut sub_consumer, ctx.namespace).await?;
+    let sub_track = sub_broadcast
+        .subscribe_track(&info)
+        .context("failed to subscribe to track")?;
+
+    let mut pub_track = dynamic
+        .requested_track()
+        .await
+        .context("publisher broadcast closed")?;
+
+    let sent_before = sessions.publisher.bytes_sent();
+    let received_before = sessions.subscriber.bytes_received();
+
+    // Each object is filled with its group's sequence, so a mixed-up or
+    // truncated one shows.
+    for sequence in 0..BACKPRESSURE_GROUPS {
+        let mut group = pub_track.append_group().context("failed to create group")?;
+        group
+            .write_frame(vec![sequence as u8; BACKPRESSURE_OBJECT_SIZE])
+            .context("failed to write frame")?;
+        group.finish().context("failed to finish group")?;
+    }
+    let written = BACKPRESSURE_GROUPS * BACKPRESSURE_OBJECT_SIZE as u64;
+
+    let mut delivered = 0;
+    let mut read = 0u64;
+    let mut max_buffered = 0;
+    let mut max_backlog = 0;
+    // Newer groups are sent first, so read each by sequence rather than in
+    // arrival order; one that's gone was dropped somewhere along the way.
+    for sequence in 0..BACKPRESSURE_GROUPS {
+        let mut group = sub_track
+            .get_group(sequence)
+            .await
+            .context("failed to read group")?
+            .with_context(|| format!("group {} was lost, after {} arrived", sequence, delivered))?;
+        let frame = group
+            .read_frame()
+            .await
+            .context("failed to read frame")?
+            .with_context(|| format!("group {} was empty", group.info.sequence))?;
+        anyhow::ensure!(
+            frame.len() == BACKPRESSURE_OBJECT_SIZE
+                && frame.iter().all(|&b| b == group.info.sequence as u8),
+            "group {} arrived altered: {} of {} bytes",
+            group.info.sequence,
+            frame.len(),
+            BACKPRESSURE_OBJECT_SIZE
+        );
+        delivered += 1;
+        read += frame.len() as u64;
+
+        let received = sessions.subscriber.bytes_received() - received_before;
+        max_buffered = max_buffered.max(received.saturating_sub(read));
+        let sent = sessions.publisher.bytes_sent() - sent_before;
+        max_backlog = max_backlog.max(written.saturating_sub(sent));
+
+        tokio::time::sleep(BACKPRESSURE_READ_DELAY).await;
+    }
+
+    let diagnostics = Diagnostics {
+        objects_expected: Some(BACKPRESSURE_GROUPS),
+        objects_delivered: Some(delivered),
+        max_buffered_bytes: Some(max_buffered),
+        publisher_backlog_bytes: Some(max_backlog),
+        bytes_transferred: Some(written),
+        ..sessions.diagnostics()
+    };
+
+    sessions.close();
+
+    Ok(diagnostics)
+}
+
+/// One connection: speaks draft-14's SETUP by hand, offering only draft-14 and
+/// a max request ID, plus the path for raw QUIC, and checks the relay's
+/// SERVER_SETUP is sane.
+///
+/// The relay must select draft-14 and grant a non-zero max request ID; a
+/// missing one means zero, which would leave us unable to subscribe to or
+/// announce anything. Draft-14 has no role parameter, and path only goes from
+/// client to relay, so neither is expected back.
+async fn test_setup_params(ctx: &TestContext<'_>) -> anyhow::Result<Diagnostics> {
+    let mut parameters = vec![
+        setup::Parameter::varint(setup::MAX_REQUEST_ID, SETUP_MAX_REQUEST_ID),
+        setup::Parameter::bytes(setup::IMPLEMENTATION, env!("CARGO_PKG_NAME")),
+    ];
+    if ctx.relay_url.scheme() != "https" {
+        parameters.push(setup::Parameter::bytes(setup::PATH, ctx.relay_url.path()));
+    }
+
+    let exchange = ctx
+        .client
+        .exchange_setup(ctx.relay_url.clone(), &parameters)
+        .await
+        .context("setup exchange failed")?;
+    let server = &exchange.server;
+
+    let diagnostics = Diagnostics {
+        connection_id: Some(exchange.id()),
+        rtt_ms: Some(millis(exchange.rtt())),
+        connect_ms: Some(millis(exchange.connect_time())),
+        setup_version: Some(match moq_lite::Version::from_code(server.version) {
+            Some(version) => version.to_string(),
+            None => format!("0x{:x}", server.version),
+        }),
+        setup_parameters: Some(
+            server
+                .parameters
+                .iter()
+                .map(|param| setup::parameter_name(param.kind))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        max_request_id: server.varint(setup::MAX_REQUEST_ID),
+        implementation: server
+            .bytes(setup::IMPLEMENTATION)
+            .map(|name| String::from_utf8_lossy(name).into_owned()),
+        ..Default::default()
+    };
+
+    anyhow::ensure!(
+        server.version == setup::DRAFT_14,
+        "relay selected version 0x{:x}, but only draft-14 (0x{:x}) was offered",
+        server.version,
+        setup::DRAFT_14
+    );
+    match diagnostics.max_request_id {
+        None => anyhow::bail!("relay sent no max request ID, so it allows no requests"),
+        Some(0) => anyhow::bail!("relay sent a max request ID of 0, so it allows no requests"),
+        Some(_) => {}
+    }
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("subscribe-*", "subscribe-error"));
+        assert!(glob_match("*-announce", "re-announce"));
+        assert!(glob_match("*announce*", "announce-subscribe"));
+        assert!(!glob_match("subscribe-*", "re-subscribe"));
+        assert!(!glob_match("*-announce", "announce-only"));
+
+        assert!(glob_match("setup-onl?", "setup-only"));
+        assert!(!glob_match("setup-onl?", "setup-onl"));
+        assert!(!glob_match("setup-?", "setup-only"));
+    }
+
+    #[test]
+    fn glob_match_literal() {
+        assert!(glob_match("setup-only", "setup-only"));
+        assert!(!glob_match("setup-only", "setup-only-ipv6"));
+        assert!(!glob_match("setup", "setup-only"));
+    }
+
+    #[test]
+    fn glob_match_empty_and_long_patterns() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "setup-only"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "setup-only"));
+        assert!(!glob_match("setup-only-ipv6", "setup-only"));
+        assert!(glob_match("setup-only*", "setup-only"));
+        assert!(!glob_match("setup-only?", "setup-only"));
+    }
+
+    #[test]
+    fn parse_timeout_override_values() {
+        assert_eq!(
+            parse_timeout_override("setup-only=2500"),
+            Ok(("setup-only".to_string(), Duration::from_millis(2500)))
+        );
+        assert_eq!(
+            parse_timeout_override("setup-only=0"),
+            Ok(("setup-only".to_string(), Duration::ZERO))
+        );
+        assert!(parse_timeout_override("setup-only").is_err());
+        assert!(parse_timeout_override("setup-only=").is_err());
+        assert!(parse_timeout_override("setup-only=-1").is_err());
+        assert!(parse_timeout_override("setup-only=1.5").is_err());
+        assert_eq!(
+            parse_timeout_override("no-such-test=1000"),
+            Err("unknown test: no-such-test".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_percent_range() {
+        assert_eq!(parse_percent("0"), Ok(0.0));
+        assert_eq!(parse_percent("2.5"), Ok(2.5));
+        assert_eq!(parse_percent("100"), Ok(100.0));
+        assert!(parse_percent("-0.1").is_err());
+        assert!(parse_percent("100.1").is_err());
+        assert!(parse_percent("NaN").is_err());
+        assert!(parse_percent("5%").is_err());
+    }
+
+    #[test]
+    fn parse_bind_addresses() {
+        assert_eq!(
+            parse_bind("127.0.0.1:5000"),
+            Ok("127.0.0.1:5000".parse().unwrap())
+        );
+        assert_eq!(parse_bind("[::1]:5000"), Ok("[::1]:5000".parse().unwrap()));
+        // A bare IP gets an ephemeral port.
+        assert_eq!(parse_bind("127.0.0.1"), Ok("127.0.0.1:0".parse().unwrap()));
+        assert_eq!(parse_bind("::"), Ok("[::]:0".parse().unwrap()));
+        assert!(parse_bind("localhost:5000").is_err());
+        assert!(parse_bind("127.0.0.1:").is_err());
+        assert!(parse_bind("").is_err());
+    }
+
+    #[tokio::test]
+    async fn closed_port_is_refused() {
+        // Bind and drop a socket to find a port nothing is listening on.
+        let addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut config = moq_native::ClientConfig::default();
+        config.tls.disable_verify = Some(true);
+        let client = connect::Client::new(&config, None, None, impair::Impairment::default())
+            .unwrap()
+            .with_retries(0);
+
+        let url = url::Url::parse(&format!("moqt://{}", addr)).unwrap();
+        let err = tokio::time::timeout(Duration::from_secs(5), client.connect(url))
+            .await
+            .expect("connecting to a closed port timed out")
+            .err()
+            .expect("connected to a closed port");
+        assert!(
+            FailureKind::classify(&err) == FailureKind::ConnectionRefused,
+            "{:?}",
+            err
+        );
+    }
 }
@@ -0,0 +1,344 @@
+//! A hand-rolled draft-14 CLIENT_SETUP/SERVER_SETUP exchange for `setup-params`.
+//!
+//! moq-lite reads the relay's SERVER_SETUP parameters and keeps them to itself,
+//! so the test speaks the handshake directly. Only draft-14 is covered: lite-03
+//! and draft-17 have no SETUP stream, and draft-15/16 are the same message
+//! without the versions list, which wouldn't tell us anything more.
+//!
+//! Both messages are a type, a 16-bit length, then the body:
+//!
+//! ```text
+//! CLIENT_SETUP (0x20) { versions: count (i) + version (i)..., parameters }
+//! SERVER_SETUP (0x21) { selected version (i), parameters }
+//! parameters = count (i) + { type (i), even ? value (i) : length (i) + bytes }...
+//! ```
+
+use anyhow::Context;
+use moq_native::web_transport_quinn;
+
+const CLIENT_SETUP: u8 = 0x20;
+const SERVER_SETUP: u8 = 0x21;
+
+/// The draft-14 version code, as offered in CLIENT_SETUP.
+pub const DRAFT_14: u64 = 0xff00000e;
+
+/// Setup parameter types, from draft-14 section 9.3.
+pub const PATH: u64 = 0x01;
+pub const MAX_REQUEST_ID: u64 = 0x02;
+pub const AUTHORIZATION_TOKEN: u64 = 0x03;
+pub const MAX_AUTH_TOKEN_CACHE_SIZE: u64 = 0x04;
+pub const AUTHORITY: u64 = 0x05;
+pub const IMPLEMENTATION: u64 = 0x07;
+
+/// A parameter type's name as reported in diagnostics, or its hex code if
+/// draft-14 doesn't define it.
+pub fn parameter_name(kind: u64) -> String {
+    match kind {
+        PATH => "path".to_string(),
+        MAX_REQUEST_ID => "max_request_id".to_string(),
+        AUTHORIZATION_TOKEN => "authorization_token".to_string(),
+        MAX_AUTH_TOKEN_CACHE_SIZE => "max_auth_token_cache_size".to_string(),
+        AUTHORITY => "authority".to_string(),
+        IMPLEMENTATION => "implementation".to_string(),
+        _ => format!("0x{:x}", kind),
+    }
+}
+
+/// A parameter's value; even types carry a varint, odd ones bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    VarInt(u64),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub kind: u64,
+    pub value: Value,
+}
+
+impl Parameter {
+    pub fn varint(kind: u64, value: u64) -> Self {
+        Self {
+            kind,
+            value: Value::VarInt(value),
+        }
+    }
+
+    pub fn bytes(kind: u64, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            kind,
+            value: Value::Bytes(value.into()),
+        }
+    }
+}
+
+/// What the relay answered with.
+#[derive(Clone, Debug)]
+pub struct ServerSetup {
+    pub version: u64,
+    /// In the order the relay sent them.
+    pub parameters: Vec<Parameter>,
+}
+
+impl ServerSetup {
+    pub fn varint(&self, kind: u64) -> Option<u64> {
+        self.parameters
+            .iter()
+            .find_map(|param| match (&param.value, param.kind == kind) {
+                (Value::VarInt(value), true) => Some(*value),
+                _ => None,
+            })
+    }
+
+    pub fn bytes(&self, kind: u64) -> Option<&[u8]> {
+        self.parameters
+            .iter()
+            .find_map(|param| match (&param.value, param.kind == kind) {
+                (Value::Bytes(value), true) => Some(value.as_slice()),
+                _ => None,
+            })
+    }
+}
+
+/// Encode a CLIENT_SETUP offering `versions`.
+pub fn client_setup(versions: &[u64], parameters: &[Parameter]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    write_varint(&mut body, versions.len() as u64)?;
+    for version in versions {
+        write_varint(&mut body, *version)?;
+    }
+    write_varint(&mut body, parameters.len() as u64)?;
+    for param in parameters {
+        write_varint(&mut body, param.kind)?;
+        match &param.value {
+            Value::VarInt(value) => write_varint(&mut body, *value)?,
+            Value::Bytes(value) => {
+                write_varint(&mut body, value.len() as u64)?;
+                body.extend_from_slice(value);
+            }
+        }
+    }
+
+    let size = u16::try_from(body.len()).context("client setup too large")?;
+    let mut msg = vec![CLIENT_SETUP];
+    msg.extend_from_slice(&size.to_be_bytes());
+    msg.extend_from_slice(&body);
+    Ok(msg)
+}
+
+/// Read and decode a SERVER_SETUP.
+pub async fn read_server_setup(
+    recv: &mut web_transport_quinn::RecvStream,
+) -> anyhow::Result<ServerSetup> {
+    let mut msg = vec![0; 3];
+    recv.read_exact(&mut msg)
+        .await
+        .context("failed to read server setup")?;
+    // A wrong type is caught decoding; its length still tells us what to read.
+    let size = u16::from_be_bytes([msg[1], msg[2]]) as usize;
+    msg.resize(3 + size, 0);
+    recv.read_exact(&mut msg[3..])
+        .await
+        .context("failed to read server setup")?;
+
+    decode_server_setup(&msg)
+}
+
+/// Decode a whole SERVER_SETUP message, type and length included.
+fn decode_server_setup(msg: &[u8]) -> anyhow::Result<ServerSetup> {
+    anyhow::ensure!(msg.len() >= 3, "truncated server setup");
+    anyhow::ensure!(
+        msg[0] == SERVER_SETUP,
+        "expected server setup (0x{:x}), got message type 0x{:x}",
+        SERVER_SETUP,
+        msg[0]
+    );
+    let size = u16::from_be_bytes([msg[1], msg[2]]) as usize;
+    let mut r = &msg[3..];
+    anyhow::ensure!(
+        r.len() == size,
+        "server setup is {} bytes, but its length says {}",
+        r.len(),
+        size
+    );
+
+    let version = read_varint(&mut r)?;
+    let count = read_varint(&mut r)?;
+    let mut parameters = Vec::new();
+    for _ in 0..count {
+        let kind = read_varint(&mut r)?;
+        let value = match kind % 2 {
+            0 => Value::VarInt(read_varint(&mut r)?),
+            _ => {
+                let len = read_varint(&mut r)? as usize;
+                anyhow::ensure!(r.len() >= len, "truncated parameter 0x{:x}", kind);
+                let (value, rest) = r.split_at(len);
+                r = rest;
+                Value::Bytes(value.to_vec())
+            }
+        };
+        anyhow::ensure!(
+            parameters
+                .iter()
+                .all(|param: &Parameter| param.kind != kind),
+            "duplicate parameter 0x{:x}",
+            kind
+        );
+        parameters.push(Parameter { kind, value });
+    }
+    anyhow::ensure!(
+        r.is_empty(),
+        "{} trailing bytes after server setup parameters",
+        r.len()
+    );
+
+    Ok(ServerSetup {
+        version,
+        parameters,
+    })
+}
+
+/// QUIC's variable-length integer encoding, in the smallest size that fits.
+fn write_varint(buf: &mut Vec<u8>, value: u64) -> anyhow::Result<()> {
+    match value {
+        0..0x40 => buf.push(value as u8),
+        0x40..0x4000 => buf.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes()),
+        0x4000..0x4000_0000 => buf.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes()),
+        0x4000_0000..0x4000_0000_0000_0000 => {
+            buf.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes())
+        }
+        _ => anyhow::bail!("{} is too large for a varint", value),
+    }
+    Ok(())
+}
+
+fn read_varint(r: &mut &[u8]) -> anyhow::Result<u64> {
+    let first = *r.first().context("truncated varint")?;
+    let len = 1 << (first >> 6);
+    anyhow::ensure!(r.len() >= len, "truncated varint");
+
+    let (bytes, rest) = r.split_at(len);
+    *r = rest;
+    Ok(bytes[1..]
+        .iter()
+        .fold((first & 0x3f) as u64, |value, byte| {
+            value << 8 | *byte as u64
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draft-14's version code as an 8-byte varint.
+    const DRAFT_14_VARINT: [u8; 8] = [0xc0, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x0e];
+
+    fn server_setup(body: &[u8]) -> Vec<u8> {
+        let mut msg = vec![SERVER_SETUP];
+        msg.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for (value, len) in [
+            (0, 1),
+            (0x3f, 1),
+            (0x40, 2),
+            (0x3fff, 2),
+            (0x4000, 4),
+            (0x3fff_ffff, 4),
+            (0x4000_0000, 8),
+            ((1 << 62) - 1, 8),
+        ] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value).unwrap();
+            assert_eq!(buf.len(), len, "{:#x}", value);
+
+            let mut r = buf.as_slice();
+            assert_eq!(read_varint(&mut r).unwrap(), value);
+            assert!(r.is_empty());
+        }
+    }
+
+    #[test]
+    fn varint_too_large() {
+        assert!(write_varint(&mut Vec::new(), 1 << 62).is_err());
+        assert!(write_varint(&mut Vec::new(), u64::MAX).is_err());
+    }
+
+    #[test]
+    fn varint_truncated() {
+        assert!(read_varint(&mut [].as_slice()).is_err());
+        assert!(read_varint(&mut [0x40].as_slice()).is_err());
+        assert!(read_varint(&mut [0xc0, 0, 0, 0].as_slice()).is_err());
+    }
+
+    #[test]
+    fn encode_client_setup() {
+        let msg = client_setup(
+            &[DRAFT_14],
+            &[
+                Parameter::varint(MAX_REQUEST_ID, 100),
+                Parameter::bytes(PATH, "/x"),
+            ],
+        )
+        .unwrap();
+
+        let mut expected = vec![CLIENT_SETUP, 0x00, 0x11, 0x01];
+        expected.extend_from_slice(&DRAFT_14_VARINT);
+        expected.extend_from_slice(&[0x02, 0x02, 0x40, 0x64, 0x01, 0x02, b'/', b'x']);
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn decode_server_setup_parameters() {
+        let mut body = DRAFT_14_VARINT.to_vec();
+        body.extend_from_slice(&[0x02, 0x02, 0x40, 0x64, 0x07, 0x03, b'a', b'b', b'c']);
+
+        let setup = decode_server_setup(&server_setup(&body)).unwrap();
+        assert_eq!(setup.version, DRAFT_14);
+        assert_eq!(setup.varint(MAX_REQUEST_ID), Some(100));
+        assert_eq!(setup.bytes(IMPLEMENTATION), Some(&b"abc"[..]));
+        assert_eq!(setup.bytes(PATH), None);
+        let kinds: Vec<_> = setup.parameters.iter().map(|param| param.kind).collect();
+        assert_eq!(kinds, [MAX_REQUEST_ID, IMPLEMENTATION]);
+    }
+
+    #[test]
+    fn decode_server_setup_errors() {
+        let decode = |body: &[u8]| {
+            let mut full = DRAFT_14_VARINT.to_vec();
+            full.extend_from_slice(body);
+            decode_server_setup(&server_setup(&full))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(decode(&[0x01, 0x07, 0x05, b'a']), "truncated parameter 0x7");
+        assert_eq!(decode(&[0x01, 0x02]), "truncated varint");
+        assert_eq!(
+            decode(&[0x02, 0x02, 0x01, 0x02, 0x02]),
+            "duplicate parameter 0x2"
+        );
+        assert_eq!(
+            decode(&[0x00, 0xaa, 0xbb]),
+            "2 trailing bytes after server setup parameters"
+        );
+    }
+
+    #[test]
+    fn decode_server_setup_framing() {
+        let msg = server_setup(&[0x01, 0x00]);
+
+        let mut client = msg.clone();
+        client[0] = CLIENT_SETUP;
+        assert!(decode_server_setup(&client).is_err());
+
+        assert!(decode_server_setup(&msg[..2]).is_err());
+        assert!(decode_server_setup(&msg[..3]).is_err());
+        assert!(decode_server_setup(&[msg.as_slice(), &[0]].concat()).is_err());
+    }
+}